@@ -1,3 +1,4 @@
+use crate::netcode::{fnv1a, BitReader, BitWriter, NetInput, FNV_OFFSET};
 use ggez::graphics::{self, Image};
 use ggez::{self, Context, GameResult};
 use serde::{Deserialize, Serialize};
@@ -25,6 +26,24 @@ impl Default for GameInput {
     }
 }
 
+// only the direction matters, so it fits in 2 bits instead of 4 bytes
+impl NetInput for GameInput {
+    fn encode_bits(&self, writer: &mut BitWriter) {
+        writer.write_bits((self.x_axis.signum() + 1) as u64, 2);
+    }
+    fn layout_probes() -> Vec<Self> {
+        (-1..=1).map(|x_axis| Self { x_axis }).collect()
+    }
+    fn decode_bits(reader: &mut BitReader<'_>) -> Option<Self> {
+        match reader.read_bits(2)? {
+            bits @ 0..=2 => Some(Self {
+                x_axis: bits as i32 - 1,
+            }),
+            _ => None,
+        }
+    }
+}
+
 pub type PlayerInputHistory = GameInput;
 
 impl GameState {
@@ -55,6 +74,12 @@ impl GameState {
         }
     }
 
+    pub fn checksum(&self) -> u64 {
+        // has to come out the same on every peer, no matter what they were built with
+        let hash = fnv1a(FNV_OFFSET, &self.p1.x.to_le_bytes());
+        fnv1a(hash, &self.p2.x.to_le_bytes())
+    }
+
     pub fn draw(&self, ctx: &mut Context, y_offset: f32) -> GameResult<()> {
         graphics::draw(
             ctx,
@@ -1,11 +1,162 @@
 mod local_history;
 mod networked_history;
+mod prediction_strategy;
+mod ring_buffer;
 
 pub use local_history::LocalHistory;
 pub use networked_history::{NetworkedHistory, PredictionResult};
+pub use prediction_strategy::{DecayToNeutral, Neutral, PredictionStrategy, RepeatLast};
 
+// closed interval of frames
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct InputRange {
     pub first: usize,
     pub last: usize,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryError {
+    // the frame didn't fit in the window of frames the history holds
+    OutsideWindow { frame: usize },
+    // the input on frame can't go through the change, like predicting a confirmed input,
+    // or cleaning out a predicted one
+    UnexpectedStatus { frame: usize, status: InputStatus },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputStatus {
+    Confirmed,
+    Predicted,
+    // a later frame arrived first, so this is just a placeholder
+    #[default]
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputWindow<'a, T> {
+    pub range: InputRange,
+    pub inputs: &'a [T],
+    // none means every input in the window is confirmed
+    statuses: Option<&'a [InputStatus]>,
+}
+
+impl<'a, T> InputWindow<'a, T> {
+    fn confirmed(range: InputRange, inputs: &'a [T]) -> Self {
+        Self {
+            range,
+            inputs,
+            statuses: None,
+        }
+    }
+    fn with_statuses(range: InputRange, inputs: &'a [T], statuses: &'a [InputStatus]) -> Self {
+        Self {
+            range,
+            inputs,
+            statuses: Some(statuses),
+        }
+    }
+
+    pub fn contains(&self, frame: usize) -> bool {
+        self.range.first <= frame && frame <= self.range.last
+    }
+
+    pub fn status(&self, frame: usize) -> Option<InputStatus> {
+        if !self.contains(frame) {
+            return None;
+        }
+        match self.statuses {
+            Some(statuses) => Some(statuses[frame - self.range.first]),
+            None => Some(InputStatus::Confirmed),
+        }
+    }
+}
+
+pub trait InputHistory<T> {
+    // a best effort to get the amt inputs ending on frame, the window returned can be shorter
+    // than asked for, or end early if we don't have that frame yet,
+    // and is none if we don't have any of the inputs asked for
+    fn request_inputs(&self, frame: usize, amt: usize) -> Option<InputWindow<'_, T>>;
+    fn set_capacity(&mut self, capacity: usize);
+    // every frame before this one has been confirmed
+    fn next_unconfirmed_frame(&self) -> usize;
+    // drops every input before frame
+    fn clean(&mut self, frame: usize) -> Result<(), HistoryError>;
+
+    fn input_status(&self, frame: usize) -> Option<InputStatus> {
+        self.request_inputs(frame, 1)
+            .and_then(|window| window.status(frame))
+    }
+    fn has_input(&self, frame: usize) -> bool {
+        match self.input_status(frame) {
+            Some(InputStatus::Confirmed) | Some(InputStatus::Predicted) => true,
+            Some(InputStatus::Missing) | None => false,
+        }
+    }
+    fn is_predicted_input(&self, frame: usize) -> bool {
+        self.input_status(frame) == Some(InputStatus::Predicted)
+    }
+    fn is_confirmed_input(&self, frame: usize) -> bool {
+        self.input_status(frame) == Some(InputStatus::Confirmed)
+    }
+}
+
+// the frames in both the requested range and the range of held frames
+fn overlapping_range(
+    frame: usize,
+    amt: usize,
+    front_frame: usize,
+    len: usize,
+) -> Option<InputRange> {
+    let first = (frame + 1).saturating_sub(amt).max(front_frame);
+    let last = frame.min((front_frame + len).checked_sub(1)?);
+    if amt > 0 && first <= last {
+        Some(InputRange { first, last })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(first: usize, last: usize) -> Option<InputRange> {
+        Some(InputRange { first, last })
+    }
+
+    #[test]
+    fn requests_get_cut_down_to_the_held_frames() {
+        // holding frames 10 to 19
+        assert_eq!(overlapping_range(15, 3, 10, 10), range(13, 15));
+        assert_eq!(overlapping_range(12, 5, 10, 10), range(10, 12));
+        assert_eq!(overlapping_range(25, 10, 10, 10), range(16, 19));
+        assert_eq!(overlapping_range(100, 1000, 10, 10), range(10, 19));
+    }
+
+    #[test]
+    fn requests_outside_the_held_frames() {
+        assert_eq!(overlapping_range(9, 5, 10, 10), None);
+        assert_eq!(overlapping_range(25, 5, 10, 10), None);
+        assert_eq!(overlapping_range(15, 0, 10, 10), None);
+        assert_eq!(overlapping_range(0, 1, 0, 0), None);
+    }
+
+    #[test]
+    fn window_statuses() {
+        let inputs = [1, 2, 3];
+        let statuses = [
+            InputStatus::Confirmed,
+            InputStatus::Missing,
+            InputStatus::Predicted,
+        ];
+        let window =
+            InputWindow::with_statuses(InputRange { first: 4, last: 6 }, &inputs, &statuses);
+
+        assert_eq!(window.status(3), None);
+        assert_eq!(window.status(5), Some(InputStatus::Missing));
+        assert_eq!(window.status(6), Some(InputStatus::Predicted));
+        assert_eq!(window.status(7), None);
+        let confirmed = InputWindow::confirmed(InputRange { first: 4, last: 6 }, &inputs);
+        assert_eq!(confirmed.status(6), Some(InputStatus::Confirmed));
+    }
+}
@@ -1,59 +1,120 @@
-use super::InputRange;
+use super::ring_buffer::RingBuffer;
+use super::{overlapping_range, HistoryError, InputHistory, InputWindow};
 
 #[derive(Debug)]
 pub struct LocalHistory<T> {
     front_frame: usize,
-    data: Vec<T>,
+    data: RingBuffer<T>,
 }
 
 impl<T: Default + Clone> LocalHistory<T> {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
+        Self::starting_at(capacity, 0)
+    }
+    // the first input added is for frame
+    pub fn starting_at(capacity: usize, frame: usize) -> Self {
         Self {
-            front_frame: 0,
-            data: vec![],
+            front_frame: frame,
+            data: RingBuffer::with_capacity(capacity),
         }
     }
 
     fn adjust_frame(&self, frame: usize) -> Option<usize> {
         frame.checked_sub(self.front_frame)
     }
-    fn adjust_idx(&self, idx: usize) -> usize {
-        idx + self.front_frame
-    }
 
-    pub fn add_input(&mut self, data: T) -> usize {
-        self.data.push(data);
-        self.front_frame + self.data.len() - 1
+    pub fn add_input(&mut self, data: T) -> Result<usize, HistoryError> {
+        let frame = self.front_frame + self.data.len();
+        self.data
+            .push(data)
+            .map_err(|_| HistoryError::OutsideWindow { frame })?;
+        Ok(frame)
     }
+}
 
-    pub fn has_input(&self, frame: usize) -> bool {
-        self.adjust_frame(frame)
-            .and_then(|frame| self.data.get(frame))
-            .is_some()
+impl<T: Default + Clone> InputHistory<T> for LocalHistory<T> {
+    fn request_inputs(&self, frame: usize, amt: usize) -> Option<InputWindow<'_, T>> {
+        let range = overlapping_range(frame, amt, self.front_frame, self.data.len())?;
+        let start_idx = self.adjust_frame(range.first)?;
+        let end_idx = self.adjust_frame(range.last)? + 1;
+
+        Some(InputWindow::confirmed(
+            range,
+            self.data.slice(start_idx..end_idx),
+        ))
     }
 
-    pub fn get_inputs(&self, frame: usize, amt: usize) -> (InputRange, &[T]) {
-        let frame = self.adjust_frame(frame).unwrap();
-        let end_idx = self.data.len().min(frame + 1);
-        let start_idx = end_idx.checked_sub(amt).unwrap_or(0);
+    fn set_capacity(&mut self, capacity: usize) {
+        self.data.set_capacity(capacity);
+    }
 
-        (
-            InputRange {
-                first: self.adjust_idx(start_idx),
-                last: self.adjust_idx(end_idx).checked_sub(1).unwrap_or(0),
-            },
-            &self.data[start_idx..end_idx],
-        )
+    // local inputs are confirmed as soon as they're added
+    fn next_unconfirmed_frame(&self) -> usize {
+        self.front_frame + self.data.len()
     }
 
-    pub fn clean(&mut self, frame: usize) {
+    // every local input is confirmed, so they can always be cleaned
+    fn clean(&mut self, frame: usize) -> Result<(), HistoryError> {
         let front_elements = self.adjust_frame(frame);
 
         if let Some(front_elements) = front_elements {
+            let front_elements = front_elements.min(self.data.len());
             if front_elements > 0 {
-                self.data.drain(0..front_elements);
-                self.front_frame = frame;
+                self.data.pop_front(front_elements);
+                self.front_frame += front_elements;
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_history::InputRange;
+
+    #[test]
+    fn inputs_get_the_next_frame() {
+        let mut history = LocalHistory::starting_at(4, 7);
+
+        assert_eq!(history.add_input(1), Ok(7));
+        assert_eq!(history.add_input(2), Ok(8));
+        assert_eq!(history.next_unconfirmed_frame(), 9);
+        assert!(history.is_confirmed_input(8));
+        assert!(!history.has_input(6));
+        assert!(!history.has_input(9));
+    }
+
+    #[test]
+    fn full_histories_refuse_input() {
+        let mut history = LocalHistory::new(2);
+        history.add_input(1).unwrap();
+        history.add_input(2).unwrap();
+
+        assert_eq!(
+            history.add_input(3),
+            Err(HistoryError::OutsideWindow { frame: 2 })
+        );
+        // cleaning makes room again
+        history.clean(1).unwrap();
+        assert_eq!(history.add_input(3), Ok(2));
+    }
+
+    #[test]
+    fn requests_after_cleaning() {
+        let mut history = LocalHistory::new(8);
+        for input in 0..6 {
+            history.add_input(input).unwrap();
+        }
+        history.clean(3).unwrap();
+
+        let window = history.request_inputs(4, 4).unwrap();
+        assert_eq!(window.range, InputRange { first: 3, last: 4 });
+        assert_eq!(window.inputs, &[3, 4]);
+        assert!(history.request_inputs(2, 3).is_none());
+        // cleaning past everything we have just empties the history
+        history.clean(100).unwrap();
+        assert!(history.request_inputs(5, 1).is_none());
+        assert_eq!(history.add_input(6), Ok(6));
     }
 }
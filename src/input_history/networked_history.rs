@@ -1,17 +1,16 @@
-use super::InputRange;
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum Canon {
-    Canon,
-    Predicted,
-    Empty,
-}
+use super::ring_buffer::RingBuffer;
+use super::{
+    overlapping_range, HistoryError, InputHistory, InputStatus, InputWindow, PredictionStrategy,
+};
 
 #[derive(Debug)]
 pub struct NetworkedHistory<T> {
     front_frame: usize,
-    canon: Vec<Canon>,
-    data: Vec<T>,
+    canon: RingBuffer<InputStatus>,
+    data: RingBuffer<T>,
+    // the input right before front_frame, so predictions still have something to go off of
+    // after everything confirmed was cleaned out
+    last_cleaned: Option<T>,
 }
 
 pub enum PredictionResult {
@@ -21,11 +20,12 @@ pub enum PredictionResult {
 }
 
 impl<T: Default + Clone + PartialEq> NetworkedHistory<T> {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
             front_frame: 0,
-            canon: vec![],
-            data: vec![],
+            canon: RingBuffer::with_capacity(capacity),
+            data: RingBuffer::with_capacity(capacity),
+            last_cleaned: None,
         }
     }
 
@@ -36,135 +36,302 @@ impl<T: Default + Clone + PartialEq> NetworkedHistory<T> {
         idx + self.front_frame
     }
 
-    pub fn add_input(&mut self, frame: usize, data: T) -> PredictionResult {
+    pub fn add_input(&mut self, frame: usize, data: T) -> Result<PredictionResult, HistoryError> {
         // this complex logic, and the array of canonicalness
         // help us not throw out data that has arrived, even if its not
         // data thats immediately necessary
         let relative_frame = self.adjust_frame(frame);
         if relative_frame.is_none() {
             // input is too far in the past, so we can't actually care about it.
-            return PredictionResult::Unpredicted;
+            return Ok(PredictionResult::Unpredicted);
         }
         let relative_frame = relative_frame.unwrap();
 
-        if relative_frame == self.data.len() {
-            self.canon.push(Canon::Canon);
-            self.data.push(data);
-            PredictionResult::Unpredicted
-        } else if relative_frame > self.data.len() {
-            self.canon.resize(relative_frame + 1, Canon::Empty);
-            self.data.resize(relative_frame + 1, Default::default());
-
-            self.canon[relative_frame] = Canon::Canon;
-            self.data[relative_frame] = data;
-            PredictionResult::Unpredicted
+        if relative_frame >= self.data.capacity() {
+            // input is too far in the future to fit, so we can't hold on to it
+            return Err(HistoryError::OutsideWindow { frame });
+        }
+
+        if relative_frame >= self.data.len() {
+            while self.data.len() <= relative_frame {
+                // can't fail, we already checked this fits
+                self.canon.push(InputStatus::Missing).ok();
+                self.data.push(Default::default()).ok();
+            }
+
+            self.canon.set(relative_frame, InputStatus::Confirmed);
+            self.data.set(relative_frame, data);
+            Ok(PredictionResult::Unpredicted)
         } else {
-            match self.canon[relative_frame] {
-                Canon::Canon => PredictionResult::Unpredicted,
-                Canon::Empty => {
-                    self.canon[relative_frame] = Canon::Canon;
-                    self.data[relative_frame] = data;
-                    PredictionResult::Unpredicted
+            match self.canon.get(relative_frame).unwrap() {
+                InputStatus::Confirmed => Ok(PredictionResult::Unpredicted),
+                InputStatus::Missing => {
+                    self.canon.set(relative_frame, InputStatus::Confirmed);
+                    self.data.set(relative_frame, data);
+                    Ok(PredictionResult::Unpredicted)
                 }
-                Canon::Predicted => {
-                    self.canon[relative_frame] = Canon::Canon;
-                    if self.data[relative_frame] == data {
-                        PredictionResult::Correct
+                InputStatus::Predicted => {
+                    self.canon.set(relative_frame, InputStatus::Confirmed);
+                    if *self.data.get(relative_frame).unwrap() == data {
+                        Ok(PredictionResult::Correct)
                     } else {
-                        self.data[relative_frame] = data;
-                        PredictionResult::Wrong
+                        self.data.set(relative_frame, data);
+                        Ok(PredictionResult::Wrong)
                     }
                 }
             }
         }
     }
-    pub fn has_input(&self, frame: usize) -> bool {
-        self.adjust_frame(frame)
-            .and_then(|frame| self.canon.get(frame))
-            .map(|canon| *canon != Canon::Empty)
-            .unwrap_or(false)
-    }
-
-    pub fn is_predicted_input(&self, frame: usize) -> bool {
-        self.adjust_frame(frame)
-            .and_then(|frame| self.canon.get(frame))
-            .map(|canon| *canon == Canon::Predicted)
-            .unwrap_or(false)
-    }
-
     pub fn is_empty_input(&self, frame: usize) -> bool {
         self.adjust_frame(frame)
             .and_then(|frame| self.canon.get(frame))
-            .map(|canon| *canon == Canon::Empty)
+            .map(|canon| *canon == InputStatus::Missing)
             .unwrap_or(true)
     }
 
-    // TODO, rename to request_inputs
-    // to show that its a best effort to get the query'd for set of inputs
-    // and not a guarentee that you get the inputs you want
-    // also input range returned is considered a closed interval
-    // TODO: deal with what happens if you can't get any inputs
-    pub fn get_inputs(&self, frame: usize, amt: usize) -> (InputRange, &[T]) {
-        let frame = self.adjust_frame(frame).unwrap();
-        let end_idx = self.data.len().min(frame + 1);
-        let start_idx = end_idx.checked_sub(amt).unwrap_or(0);
-
-        (
-            InputRange {
-                first: self.adjust_idx(start_idx),
-                last: self.adjust_idx(end_idx - 1),
+    // strategies only ever see the run of confirmed inputs leading up to the last one,
+    // predicted and missing inputs are just guesses and placeholders
+    fn predicted_input(&self, relative_frame: usize, strategy: &dyn PredictionStrategy<T>) -> T {
+        let is_confirmed = |idx: &usize| self.canon.get(*idx) == Some(&InputStatus::Confirmed);
+        let last_canon = (0..relative_frame.min(self.canon.len()))
+            .rev()
+            .find(is_confirmed);
+        match last_canon {
+            Some(idx) => {
+                let first_canon = (0..idx)
+                    .rev()
+                    .find(|idx| !is_confirmed(idx))
+                    .map_or(0, |idx| idx + 1);
+                strategy.predict(self.data.slice(first_canon..idx + 1), relative_frame - idx)
+            }
+            None => match &self.last_cleaned {
+                Some(input) => strategy.predict(std::slice::from_ref(input), relative_frame + 1),
+                None => strategy.predict(&[], relative_frame + 1),
             },
-            &self.data[start_idx..end_idx],
-        )
-    }
-    pub fn predict(&mut self, frame: usize) {
-        let frame = self.adjust_frame(frame).unwrap();
-        let data = frame
-            .checked_sub(1)
-            .and_then(|frame| self.data.get(frame))
-            .cloned()
-            .unwrap_or_default();
-
-        if frame == self.data.len() {
-            self.canon.push(Canon::Predicted);
-            self.data.push(data);
+        }
+    }
+
+    // only empty inputs can be predicted
+    pub fn predict(
+        &mut self,
+        frame: usize,
+        strategy: &dyn PredictionStrategy<T>,
+    ) -> Result<(), HistoryError> {
+        let relative_frame = self
+            .adjust_frame(frame)
+            .ok_or(HistoryError::OutsideWindow { frame })?;
+        let data = self.predicted_input(relative_frame, strategy);
+
+        if relative_frame == self.data.len() {
+            self.canon
+                .push(InputStatus::Predicted)
+                .map_err(|_| HistoryError::OutsideWindow { frame })?;
+            self.data.push(data).ok();
         } else {
-            assert_eq!(
-                self.canon[frame],
-                Canon::Empty,
-                "Predicted input should not already be predicted or canon."
-            );
-
-            self.canon[frame] = Canon::Predicted;
-            self.data[frame] = data;
+            match self.canon.get(relative_frame) {
+                Some(InputStatus::Missing) => (),
+                Some(status) => {
+                    return Err(HistoryError::UnexpectedStatus {
+                        frame,
+                        status: *status,
+                    })
+                }
+                None => return Err(HistoryError::OutsideWindow { frame }),
+            }
+
+            self.canon.set(relative_frame, InputStatus::Predicted);
+            self.data.set(relative_frame, data);
         }
+        Ok(())
     }
 
-    pub fn repredict(&mut self, frame: usize) {
-        let frame = self.adjust_frame(frame).unwrap();
-        let data = frame
-            .checked_sub(1)
-            .and_then(|frame| self.data.get(frame))
-            .cloned()
-            .unwrap_or_default();
+    // only predicted inputs can be repredicted
+    pub fn repredict(
+        &mut self,
+        frame: usize,
+        strategy: &dyn PredictionStrategy<T>,
+    ) -> Result<(), HistoryError> {
+        let relative_frame = self
+            .adjust_frame(frame)
+            .ok_or(HistoryError::OutsideWindow { frame })?;
+        match self.canon.get(relative_frame) {
+            Some(InputStatus::Predicted) => (),
+            Some(status) => {
+                return Err(HistoryError::UnexpectedStatus {
+                    frame,
+                    status: *status,
+                })
+            }
+            None => return Err(HistoryError::OutsideWindow { frame }),
+        }
+
+        let data = self.predicted_input(relative_frame, strategy);
+        self.data.set(relative_frame, data);
+        Ok(())
+    }
+}
+
+impl<T: Default + Clone + PartialEq> InputHistory<T> for NetworkedHistory<T> {
+    fn request_inputs(&self, frame: usize, amt: usize) -> Option<InputWindow<'_, T>> {
+        let range = overlapping_range(frame, amt, self.front_frame, self.data.len())?;
+        let start_idx = self.adjust_frame(range.first)?;
+        let end_idx = self.adjust_frame(range.last)? + 1;
+
+        Some(InputWindow::with_statuses(
+            range,
+            self.data.slice(start_idx..end_idx),
+            self.canon.slice(start_idx..end_idx),
+        ))
+    }
 
-        assert_eq!(self.canon[frame], Canon::Predicted);
-        self.data[frame] = data;
+    fn set_capacity(&mut self, capacity: usize) {
+        self.canon.set_capacity(capacity);
+        self.data.set_capacity(capacity);
     }
 
-    pub fn clean(&mut self, frame: usize) {
+    fn next_unconfirmed_frame(&self) -> usize {
+        let confirmed = (0..self.canon.len())
+            .take_while(|idx| *self.canon.get(*idx).unwrap() == InputStatus::Confirmed)
+            .count();
+        self.adjust_idx(confirmed)
+    }
+
+    // predicted or missing inputs still have to be confirmed, so they can't be cleaned
+    fn clean(&mut self, frame: usize) -> Result<(), HistoryError> {
         let front_elements = self.adjust_frame(frame);
 
         if let Some(front_elements) = front_elements {
+            let front_elements = front_elements.min(self.data.len());
             if front_elements > 0 {
-                self.data.drain(0..front_elements);
-                let test = self
+                if let Some((idx, status)) = self
                     .canon
-                    .drain(0..front_elements)
-                    .all(|item| item == Canon::Canon);
-                assert!(test, "dropped off inputs that were predicted or empty");
-                self.front_frame = frame;
+                    .slice(0..front_elements)
+                    .iter()
+                    .enumerate()
+                    .find(|(_, status)| **status != InputStatus::Confirmed)
+                {
+                    return Err(HistoryError::UnexpectedStatus {
+                        frame: self.adjust_idx(idx),
+                        status: *status,
+                    });
+                }
+                self.last_cleaned = self.data.get(front_elements - 1).cloned();
+                self.canon.pop_front(front_elements);
+                self.data.pop_front(front_elements);
+                self.front_frame += front_elements;
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // hands back the number of frames it saw, and remembers the history it was given
+    #[derive(Default)]
+    struct Recorder {
+        histories: RefCell<Vec<(Vec<i32>, usize)>>,
+    }
+
+    impl PredictionStrategy<i32> for Recorder {
+        fn predict(&self, history: &[i32], frames_ahead: usize) -> i32 {
+            self.histories
+                .borrow_mut()
+                .push((history.to_vec(), frames_ahead));
+            -1
+        }
+    }
+
+    impl Recorder {
+        fn last(&self) -> (Vec<i32>, usize) {
+            self.histories.borrow().last().cloned().unwrap()
+        }
+    }
+
+    #[test]
+    fn predictions_only_see_confirmed_inputs() {
+        let mut history = NetworkedHistory::new(16);
+        let strategy = Recorder::default();
+        history.add_input(0, 1).unwrap();
+        history.add_input(1, 2).unwrap();
+        // frame 2 is a placeholder until it arrives
+        history.add_input(3, 4).unwrap();
+        history.add_input(4, 5).unwrap();
+
+        history.predict(2, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![1, 2], 1));
+
+        history.predict(5, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![4, 5], 1));
+
+        // the earlier prediction isn't part of the history either
+        history.predict(6, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![4, 5], 2));
+    }
+
+    #[test]
+    fn predictions_without_confirmed_inputs() {
+        let mut history = NetworkedHistory::new(16);
+        let strategy = Recorder::default();
+
+        history.predict(0, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![], 1));
+    }
+
+    #[test]
+    fn cleaning_keeps_the_last_confirmed_input() {
+        let mut history = NetworkedHistory::new(16);
+        let strategy = Recorder::default();
+        history.add_input(0, 1).unwrap();
+        history.add_input(1, 2).unwrap();
+        history.predict(2, &strategy).unwrap();
+
+        history.clean(2).unwrap();
+        history.repredict(2, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![2], 1));
+
+        history.predict(3, &strategy).unwrap();
+        assert_eq!(strategy.last(), (vec![2], 2));
+    }
+
+    #[test]
+    fn unconfirmed_inputs_cant_be_cleaned() {
+        let mut history = NetworkedHistory::new(16);
+        history.add_input(0, 1).unwrap();
+        history.predict(1, &Recorder::default()).unwrap();
+
+        assert_eq!(
+            history.clean(2),
+            Err(HistoryError::UnexpectedStatus {
+                frame: 1,
+                status: InputStatus::Predicted
+            })
+        );
+        assert_eq!(history.next_unconfirmed_frame(), 1);
+    }
+
+    #[test]
+    fn confirming_predictions() {
+        let mut history = NetworkedHistory::new(16);
+        let strategy = |_: &[i32], _: usize| 7;
+        history.predict(0, &strategy).unwrap();
+        history.predict(1, &strategy).unwrap();
+
+        assert!(matches!(
+            history.add_input(0, 7),
+            Ok(PredictionResult::Correct)
+        ));
+        assert!(matches!(
+            history.add_input(1, 3),
+            Ok(PredictionResult::Wrong)
+        ));
+        assert_eq!(history.request_inputs(1, 2).unwrap().inputs, &[7, 3]);
+        assert_eq!(
+            history.add_input(16, 0).err(),
+            Some(HistoryError::OutsideWindow { frame: 16 })
+        );
     }
 }
@@ -0,0 +1,84 @@
+// decides what input a networked player most likely pressed on a frame we haven't heard about yet
+pub trait PredictionStrategy<T> {
+    // history is a run of confirmed inputs on consecutive frames, ending with the most recent one,
+    // and frames_ahead is how many frames past that input the predicted frame is,
+    // history is empty if nothing was confirmed yet
+    fn predict(&self, history: &[T], frames_ahead: usize) -> T;
+}
+
+// usually right for fighting games, people tend to hold buttons for multiple frames
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepeatLast;
+
+impl<T: Clone + Default> PredictionStrategy<T> for RepeatLast {
+    fn predict(&self, history: &[T], _frames_ahead: usize) -> T {
+        history.last().cloned().unwrap_or_default()
+    }
+}
+
+// always predicts the default input, good for inputs that are only held for a frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neutral;
+
+impl<T: Default> PredictionStrategy<T> for Neutral {
+    fn predict(&self, _history: &[T], _frames_ahead: usize) -> T {
+        T::default()
+    }
+}
+
+// repeats the last input for a few frames, then assumes it was let go
+#[derive(Debug, Clone, Copy)]
+pub struct DecayToNeutral {
+    pub frames: usize,
+}
+
+impl<T: Clone + Default> PredictionStrategy<T> for DecayToNeutral {
+    fn predict(&self, history: &[T], frames_ahead: usize) -> T {
+        if frames_ahead <= self.frames {
+            history.last().cloned().unwrap_or_default()
+        } else {
+            T::default()
+        }
+    }
+}
+
+impl<T, F: Fn(&[T], usize) -> T> PredictionStrategy<T> for F {
+    fn predict(&self, history: &[T], frames_ahead: usize) -> T {
+        self(history, frames_ahead)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_last_repeats_the_last_input() {
+        assert_eq!(RepeatLast.predict(&[1, 2, 3], 5), 3);
+        assert_eq!(RepeatLast.predict(&[] as &[i32], 1), 0);
+    }
+
+    #[test]
+    fn neutral_ignores_history() {
+        assert_eq!(Neutral.predict(&[1, 2, 3], 1), 0);
+    }
+
+    #[test]
+    fn decay_to_neutral_lets_go_after_a_while() {
+        let strategy = DecayToNeutral { frames: 2 };
+
+        assert_eq!(strategy.predict(&[4, 5], 1), 5);
+        assert_eq!(strategy.predict(&[4, 5], 2), 5);
+        assert_eq!(strategy.predict(&[4, 5], 3), 0);
+        assert_eq!(strategy.predict(&[] as &[i32], 1), 0);
+    }
+
+    #[test]
+    fn closures_are_strategies() {
+        let strategy = |history: &[i32], frames_ahead: usize| {
+            history.last().cloned().unwrap_or_default() + frames_ahead as i32
+        };
+
+        assert_eq!(strategy.predict(&[10], 3), 13);
+    }
+}
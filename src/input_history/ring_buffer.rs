@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+// a fixed capacity queue that never allocates after being created
+// every element is stored twice, capacity apart, so any window of it can be handed out as a slice
+#[derive(Debug)]
+pub struct RingBuffer<T> {
+    data: Vec<T>,
+    front: usize,
+    len: usize,
+}
+
+impl<T: Default + Clone> RingBuffer<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: vec![T::default(); capacity.max(1) * 2],
+            front: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.len() / 2
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    fn physical_idx(&self, idx: usize) -> usize {
+        (self.front + idx) % self.capacity()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx < self.len {
+            Some(&self.data[self.front + idx])
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, idx: usize, value: T) {
+        assert!(idx < self.len, "Can't set past the end of a ring buffer.");
+        let physical_idx = self.physical_idx(idx);
+        let capacity = self.capacity();
+        self.data[physical_idx + capacity] = value.clone();
+        self.data[physical_idx] = value;
+    }
+
+    // hands the value back if there's no room for it
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        self.len += 1;
+        self.set(self.len - 1, value);
+        Ok(())
+    }
+
+    pub fn pop_front(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.front = self.physical_idx(count);
+        self.len -= count;
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> &[T] {
+        assert!(
+            range.end <= self.len,
+            "Can't slice past the end of a ring buffer."
+        );
+        &self.data[self.front + range.start..self.front + range.end]
+    }
+
+    // reallocates, so only do this when settings change, never grows smaller than what it holds
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let mut resized = Self::with_capacity(capacity.max(self.len));
+        for value in self.slice(0..self.len) {
+            resized.push(value.clone()).ok();
+        }
+        *self = resized;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(capacity: usize, values: std::ops::Range<i32>) -> RingBuffer<i32> {
+        let mut buffer = RingBuffer::with_capacity(capacity);
+        for value in values {
+            buffer.push(value).unwrap();
+        }
+        buffer
+    }
+
+    #[test]
+    fn slices_stay_whole_across_the_wraparound() {
+        let mut buffer = filled(4, 0..4);
+
+        buffer.pop_front(3);
+        for value in 4..7 {
+            buffer.push(value).unwrap();
+        }
+
+        assert_eq!(buffer.slice(0..4), &[3, 4, 5, 6]);
+        assert_eq!(buffer.slice(1..3), &[4, 5]);
+        assert_eq!(buffer.get(3), Some(&6));
+        assert_eq!(buffer.get(4), None);
+    }
+
+    #[test]
+    fn full_buffers_hand_the_value_back() {
+        let mut buffer = filled(2, 0..2);
+
+        assert!(buffer.is_full());
+        assert_eq!(buffer.push(2), Err(2));
+        buffer.pop_front(1);
+        assert_eq!(buffer.push(2), Ok(()));
+    }
+
+    #[test]
+    fn set_writes_both_copies() {
+        let mut buffer = filled(3, 0..3);
+        buffer.pop_front(2);
+        buffer.push(3).unwrap();
+        buffer.push(4).unwrap();
+
+        buffer.set(1, 30);
+
+        assert_eq!(buffer.slice(0..3), &[2, 30, 4]);
+    }
+
+    #[test]
+    fn resizing_keeps_the_values_in_order() {
+        let mut buffer = filled(3, 0..3);
+        buffer.pop_front(2);
+        buffer.push(3).unwrap();
+
+        buffer.set_capacity(5);
+        assert_eq!(buffer.capacity(), 5);
+        assert_eq!(buffer.slice(0..2), &[2, 3]);
+
+        // can't shrink past what it holds
+        buffer.set_capacity(1);
+        assert_eq!(buffer.capacity(), 2);
+        assert_eq!(buffer.slice(0..2), &[2, 3]);
+    }
+
+    #[test]
+    fn popping_more_than_it_holds_empties_it() {
+        let mut buffer = filled(3, 0..2);
+
+        buffer.pop_front(5);
+
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.slice(0..0), &[] as &[i32]);
+    }
+}
@@ -2,13 +2,68 @@ mod game;
 mod input_history;
 mod net_client;
 mod netcode;
+mod replay_runner;
 mod rollback_runner;
+mod session;
 
-use ggez::event;
+use ggez::event::{self, EventHandler};
 use ggez::ContextBuilder;
+use net_client::{HandshakeError, Hello};
+use netcode::Replay;
+
+fn handshake_error(error: HandshakeError) -> std::io::Error {
+    match error {
+        HandshakeError::Io(error) => error,
+        error => std::io::Error::new(std::io::ErrorKind::ConnectionRefused, error),
+    }
+}
+
+fn run<Handler: EventHandler>(
+    name: &str,
+    create: impl FnOnce(&mut ggez::Context) -> Handler,
+) -> std::io::Result<()> {
+    let resource_dir = if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
+        let mut path = std::path::PathBuf::from(manifest_dir);
+        path.push(".");
+        path
+    } else {
+        std::path::PathBuf::from("./")
+    };
+    // Make a Context.
+    let (mut ctx, mut event_loop) = ContextBuilder::new(name, "Cool Game Author")
+        .add_resource_path(resource_dir)
+        .build()
+        .expect("aieee, could not create ggez context!");
+
+    // Create an instance of your event handler.
+    // Usually, you should provide it with the Context object to
+    // use when setting your game up.
+
+    let mut my_game = create(&mut ctx);
+
+    // Run!
+    match event::run(&mut ctx, &mut event_loop, &mut my_game) {
+        Ok(_) => println!("Exited cleanly."),
+        Err(e) => println!("Error occured: {}", e),
+    }
+    Ok(())
+}
 
 fn main() -> std::io::Result<()> {
     let mut input = String::new();
+    println!("Watch the last replay (y/N)?");
+    std::io::stdin().read_line(&mut input).unwrap();
+    if input.trim() == "y" {
+        let file = std::fs::File::open(rollback_runner::REPLAY_PATH)?;
+        let replay = Replay::load(std::io::BufReader::new(file))?;
+        return run("my_game replay", |ctx| {
+            replay_runner::ReplayRunner::new(ctx, replay)
+        });
+    }
+
+    let hello =
+        Hello::new::<game::GameInput>(rollback_runner::GAME_ID, rollback_runner::NETCODE_SETTINGS);
+    input.clear();
     println!("Host (Y/n)?");
     std::io::stdin().read_line(&mut input).unwrap();
 
@@ -37,14 +92,17 @@ fn main() -> std::io::Result<()> {
                 .unwrap_or("127.0.0.1:10800".to_owned())
         };
 
-        let mut client = net_client::TestNetClient::host(&ip)?;
+        let mut client = net_client::NetClient::host(&ip)?;
         println!("Input player (1/2):");
         input.clear();
         std::io::stdin().read_line(&mut input).unwrap();
         let selected_player = input.trim().parse::<i32>().unwrap() == 1;
-        client.write_tcp(&!selected_player)?;
-        // TODO its dropping the host player select packet.
-        (client, selected_player)
+        // the other side gets whichever player we didn't pick
+        let (local_player, remote_player) = if selected_player { (0, 1) } else { (1, 0) };
+        let handshake = client
+            .host_handshake(&hello, local_player, remote_player)
+            .map_err(handshake_error)?;
+        (client, handshake.local_player == 0)
     } else {
         println!("Input target ip (defaults to 127.0.0.1:10800):");
         input.clear();
@@ -52,37 +110,12 @@ fn main() -> std::io::Result<()> {
         if input.trim().is_empty() {
             input = "127.0.0.1:10800".to_owned();
         }
-        let mut client = net_client::TestNetClient::connect(&input.trim())?;
-        let assigned_player: bool = client.read_tcp()?;
-        (client, assigned_player)
-    };
-
-    let resource_dir = if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
-        let mut path = std::path::PathBuf::from(manifest_dir);
-        path.push(".");
-        path
-    } else {
-        std::path::PathBuf::from("./")
+        let mut client = net_client::NetClient::connect(&input.trim())?;
+        let handshake = client.connect_handshake(&hello).map_err(handshake_error)?;
+        (client, handshake.local_player == 0)
     };
-    // Make a Context.
-    let (mut ctx, mut event_loop) = ContextBuilder::new(
-        &format!("my_game {}", if player { 1 } else { 2 }),
-        "Cool Game Author",
-    )
-    .add_resource_path(resource_dir)
-    .build()
-    .expect("aieee, could not create ggez context!");
-
-    // Create an instance of your event handler.
-    // Usually, you should provide it with the Context object to
-    // use when setting your game up.
-
-    let mut my_game = rollback_runner::RollbackRunner::new(&mut ctx, player, client);
 
-    // Run!
-    match event::run(&mut ctx, &mut event_loop, &mut my_game) {
-        Ok(_) => println!("Exited cleanly."),
-        Err(e) => println!("Error occured: {}", e),
-    }
-    Ok(())
+    run(&format!("my_game {}", if player { 1 } else { 2 }), |ctx| {
+        rollback_runner::RollbackRunner::new(ctx, player, client)
+    })
 }
@@ -1,32 +1,110 @@
-pub mod leaky_net_client;
+mod control_channel;
+mod fragmentation;
+mod handshake;
+#[cfg(test)]
+mod memory_transport;
+mod transport;
 
+pub use control_channel::{ControlChannel, SetupMessage};
+pub use handshake::{Handshake, HandshakeError, Hello, NetcodeSettings, RejectReason};
+#[cfg(test)]
+pub use memory_transport::{MemoryHub, MemoryTransport};
+pub use transport::Transport;
+
+use fragmentation::{Fragmenter, Reassembler};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::io;
-use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
 
-pub type TestNetClient = leaky_net_client::LeakyNetClient;
+// small enough to get through most routers without ip fragmentation
+pub const DEFAULT_MTU: usize = 1200;
+// the largest payload a udp datagram can hold
+pub const MAX_MTU: usize = 65507;
+pub const MIN_MTU: usize = fragmentation::HEADER_SIZE + 1;
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
 
 //consider channging buffer to a Cell or RefCell to allow internal mutation
 pub struct NetClient {
     pub udp_socket: UdpSocket,
-    pub buffer: [u8; 128],
-    tcp_stream: TcpStream,
+    // always big enough for any datagram, so a peer with a bigger mtu doesn't get truncated
+    buffer: Vec<u8>,
+    control_channel: ControlChannel<TcpStream>,
+    mtu: usize,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler,
+    dropped_datagrams: usize,
 }
 
 impl NetClient {
-    pub fn send<T: Serialize>(&self, data: &T) -> io::Result<usize> {
+    fn new(udp_socket: UdpSocket, tcp_stream: TcpStream) -> Self {
+        NetClient {
+            udp_socket,
+            buffer: vec![0; MAX_MTU],
+            control_channel: ControlChannel::new(tcp_stream),
+            mtu: DEFAULT_MTU,
+            fragmenter: Fragmenter::new(),
+            reassembler: Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT),
+            dropped_datagrams: 0,
+        }
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+    // the most bytes sent in a single datagram, packets bigger than this get split up
+    pub fn set_mtu(&mut self, value: usize) -> io::Result<()> {
+        if !(MIN_MTU..=MAX_MTU).contains(&value) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("mtu {} is outside of {}..={}", value, MIN_MTU, MAX_MTU),
+            ));
+        }
+        self.mtu = value;
+        Ok(())
+    }
+    // sending anything bigger than this is an error
+    pub fn max_packet_size(&self) -> usize {
+        fragmentation::max_packet_size(self.mtu)
+    }
+    pub fn reassembly_timeout(&self) -> Duration {
+        self.reassembler.timeout()
+    }
+    // how long to wait on the rest of a split up packet before throwing it out
+    pub fn set_reassembly_timeout(&mut self, value: Duration) {
+        self.reassembler.set_timeout(value);
+    }
+    // datagrams that were too short or malformed to be part of a packet
+    pub fn dropped_datagrams(&self) -> usize {
+        self.dropped_datagrams
+    }
+
+    pub fn send<T: Serialize>(&mut self, data: &T) -> io::Result<usize> {
         let data = bincode::serialize(data).map_err(|_| {
             io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "bincode serialization failed before sending a packet",
             )
         })?;
-        self.udp_socket.send(&data)
+        self.send_bytes(&data)?;
+        Ok(data.len())
+    }
+    fn send_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        for datagram in self.fragmenter.fragment(data, self.mtu)? {
+            self.udp_socket.send(&datagram)?;
+        }
+        Ok(())
     }
     pub fn recv<T: DeserializeOwned>(&mut self) -> io::Result<T> {
-        let len = self.udp_socket.recv(&mut self.buffer)?;
-        let data = bincode::deserialize::<T>(&self.buffer[0..len]).map_err(|_| {
+        // keeps going until a whole packet is in, or the socket would block
+        let packet = loop {
+            let len = self.udp_socket.recv(&mut self.buffer)?;
+            if let Some(packet) = self.reassemble(len) {
+                break packet;
+            }
+        };
+        let data = bincode::deserialize::<T>(&packet).map_err(|_| {
             io::Error::new(
                 io::ErrorKind::InvalidData,
                 "bincode deserialization failed after recieving a packet",
@@ -35,50 +113,154 @@ impl NetClient {
         Ok(data)
     }
 
+    // anyone can send us a datagram, so a bad one gets counted and skipped instead of
+    // failing the receive
+    fn reassemble(&mut self, len: usize) -> Option<Vec<u8>> {
+        match self.reassembler.insert(&self.buffer[..len], Instant::now()) {
+            Ok(packet) => packet,
+            Err(_) => {
+                self.dropped_datagrams += 1;
+                None
+            }
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.udp_socket.peer_addr()
+    }
+
     pub fn write_tcp<T: Serialize>(&mut self, data: &T) -> io::Result<usize> {
-        use std::io::Write;
-        self.tcp_stream
-            .write(&bincode::serialize(data).map_err(|_| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "bincode serialization failed after recieving a packet",
-                )
-            })?)
+        self.control_channel.send(data)
     }
+    // waits until a whole message arrives
     pub fn read_tcp<T: DeserializeOwned>(&mut self) -> io::Result<T> {
-        use std::io::Read;
-        let size_read = self.tcp_stream.read(&mut self.buffer)?;
-        bincode::deserialize::<T>(&self.buffer[..size_read]).map_err(|_| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                "bincode deserialization failed after recieving a packet",
-            )
-        })
+        self.control_channel.get_ref().set_nonblocking(false)?;
+        self.control_channel.recv()
+    }
+    // Ok(None) if a whole message hasn't arrived yet
+    pub fn try_read_tcp<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        self.control_channel.get_ref().set_nonblocking(true)?;
+        self.control_channel.try_recv()
+    }
+
+    // has to happen before anything else is sent, so incompatible peers never start playing
+    pub fn host_handshake(
+        &mut self,
+        local: &Hello,
+        local_player: usize,
+        remote_player: usize,
+    ) -> Result<Handshake, HandshakeError> {
+        self.control_channel.get_ref().set_nonblocking(false)?;
+        handshake::host(
+            &mut self.control_channel,
+            local,
+            local_player,
+            remote_player,
+        )
+    }
+    pub fn connect_handshake(&mut self, local: &Hello) -> Result<Handshake, HandshakeError> {
+        self.control_channel.get_ref().set_nonblocking(false)?;
+        handshake::connect(&mut self.control_channel, local)
     }
 
     pub fn connect<A: ToSocketAddrs + Copy + std::fmt::Debug>(target_addr: A) -> io::Result<Self> {
         let tcp_stream = TcpStream::connect(target_addr)?;
+        // pings go over the control channel, so they can't wait on more data to send
+        tcp_stream.set_nodelay(true)?;
         let local_addr = tcp_stream.local_addr()?;
         let udp_socket = UdpSocket::bind(local_addr)?;
         udp_socket.connect(target_addr)?;
         udp_socket.set_nonblocking(true)?;
-        Ok(NetClient {
-            udp_socket,
-            buffer: [0; 128],
-            tcp_stream,
-        })
+        Ok(NetClient::new(udp_socket, tcp_stream))
     }
     pub fn host<A: ToSocketAddrs + Copy + std::fmt::Debug>(local_addr: A) -> io::Result<Self> {
         let tcp_listener = TcpListener::bind(local_addr)?;
         let (tcp_stream, target_addr) = tcp_listener.accept()?;
+        tcp_stream.set_nodelay(true)?;
         let local_addr = tcp_stream.local_addr()?;
         let udp_socket = UdpSocket::bind(local_addr)?;
         udp_socket.connect(target_addr)?;
         udp_socket.set_nonblocking(true)?;
-        Ok(NetClient {
-            udp_socket,
-            buffer: [0; 128],
-            tcp_stream,
-        })
+        Ok(NetClient::new(udp_socket, tcp_stream))
+    }
+}
+
+impl Transport for NetClient {
+    type Peer = SocketAddr;
+
+    fn send_to(&mut self, peer: SocketAddr, data: &[u8]) -> io::Result<()> {
+        // the udp socket is connected, so it can only talk to the one peer
+        if peer != self.peer_addr()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can only send to the connected peer",
+            ));
+        }
+        match self.send_bytes(data) {
+            Ok(()) => Ok(()),
+            // same as the packet getting dropped
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+    fn recv_from(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        loop {
+            match self.udp_socket.recv_from(&mut self.buffer) {
+                Ok((len, peer)) => {
+                    if let Some(packet) = self.reassemble(len) {
+                        return Ok(Some((peer, packet)));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // two clients talking over loopback
+    fn pair() -> (NetClient, NetClient) {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connecting = TcpStream::connect(tcp_listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = tcp_listener.accept().unwrap();
+        let sockets: Vec<_> = (0..2)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        for (socket, other) in sockets.iter().zip(sockets.iter().rev()) {
+            socket.connect(other.local_addr().unwrap()).unwrap();
+            socket.set_nonblocking(true).unwrap();
+        }
+        let mut sockets = sockets.into_iter();
+        (
+            NetClient::new(sockets.next().unwrap(), connecting),
+            NetClient::new(sockets.next().unwrap(), accepted),
+        )
+    }
+
+    #[test]
+    fn junk_datagrams_are_skipped() {
+        let (mut first, mut second) = pair();
+        let second_addr = second.udp_socket.local_addr().unwrap();
+        let first_addr = first.udp_socket.local_addr().unwrap();
+
+        // too short to even have a fragment header
+        first.udp_socket.send(&[1]).unwrap();
+        first.send_to(second_addr, &[1, 2, 3]).unwrap();
+
+        let mut received = None;
+        for _ in 0..100 {
+            received = second.recv_from().unwrap();
+            if received.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(received, Some((first_addr, vec![1, 2, 3])));
+        assert_eq!(second.dropped_datagrams(), 1);
     }
 }
@@ -0,0 +1,259 @@
+use super::{Hello, RejectReason};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+// the length prefix is a big endian u32
+const PREFIX_SIZE: usize = 4;
+// nothing sent over the control channel comes close to this, anything bigger is garbage
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+// everything sent over the control channel to get a session going
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SetupMessage {
+    Hello(Hello),
+    // the peer didn't match, sent right before hanging up
+    Reject(RejectReason),
+    // the index of the player the receiver controls
+    AssignPlayer(usize),
+    // sent back once the assignment arrived, nothing gets simulated before this
+    Ready,
+}
+
+// a stream only promises bytes in order, so every message is prefixed with its length,
+// that way reads always hand back whole messages, no matter how they got split up or joined
+pub struct ControlChannel<S> {
+    stream: S,
+    incoming: Vec<u8>,
+    buffer: [u8; 512],
+}
+
+impl<S: Read + Write> ControlChannel<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            incoming: Vec::new(),
+            buffer: [0; 512],
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn send<T: Serialize>(&mut self, message: &T) -> io::Result<usize> {
+        let data = bincode::serialize(message).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bincode serialization failed before sending a message",
+            )
+        })?;
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "message of {} bytes is over the maximum of {} bytes",
+                    data.len(),
+                    MAX_MESSAGE_SIZE
+                ),
+            ));
+        }
+        let mut framed = Vec::with_capacity(PREFIX_SIZE + data.len());
+        framed.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        framed.extend_from_slice(&data);
+        self.stream.write_all(&framed)?;
+        self.stream.flush()?;
+        Ok(framed.len())
+    }
+
+    // waits for a whole message, if the stream is non-blocking this can still return WouldBlock,
+    // but anything read before that is kept for the next call
+    pub fn recv<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        loop {
+            if let Some(message) = self.take_message()? {
+                return Self::decode(&message);
+            }
+            self.fill()?;
+        }
+    }
+
+    // Ok(None) if a whole message hasn't arrived yet, the stream has to be non-blocking
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        loop {
+            if let Some(message) = self.take_message()? {
+                return Self::decode(&message).map(Some);
+            }
+            match self.fill() {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        match self.stream.read(&mut self.buffer) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the control channel was closed",
+            )),
+            Ok(len) => {
+                self.incoming.extend_from_slice(&self.buffer[..len]);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn take_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.incoming.len() < PREFIX_SIZE {
+            return Ok(None);
+        }
+        let mut prefix = [0; PREFIX_SIZE];
+        prefix.copy_from_slice(&self.incoming[..PREFIX_SIZE]);
+        let len = u32::from_be_bytes(prefix) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "recieved a message of {} bytes, over the maximum of {} bytes",
+                    len, MAX_MESSAGE_SIZE
+                ),
+            ));
+        }
+        if self.incoming.len() < PREFIX_SIZE + len {
+            return Ok(None);
+        }
+        let message = self.incoming[PREFIX_SIZE..PREFIX_SIZE + len].to_vec();
+        self.incoming.drain(..PREFIX_SIZE + len);
+        Ok(Some(message))
+    }
+
+    fn decode<T: DeserializeOwned>(message: &[u8]) -> io::Result<T> {
+        bincode::deserialize::<T>(message).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bincode deserialization failed after recieving a message",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // hands out the queued reads one at a time, and would block once they run out
+    #[derive(Default)]
+    struct ScriptedStream {
+        reads: VecDeque<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for ScriptedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(mut data) => {
+                    let len = data.len().min(buf.len());
+                    buf[..len].copy_from_slice(&data[..len]);
+                    if len < data.len() {
+                        self.reads.push_front(data.split_off(len));
+                    }
+                    Ok(len)
+                }
+                None => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+    impl Write for ScriptedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn framed(messages: &[SetupMessage]) -> Vec<u8> {
+        let mut channel = ControlChannel::new(ScriptedStream::default());
+        for message in messages {
+            channel.send(message).unwrap();
+        }
+        channel.stream.written
+    }
+
+    fn channel_reading(reads: Vec<Vec<u8>>) -> ControlChannel<ScriptedStream> {
+        ControlChannel::new(ScriptedStream {
+            reads: reads.into(),
+            written: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn coalesced_messages_come_out_separately() {
+        let data = framed(&[SetupMessage::AssignPlayer(1), SetupMessage::Ready]);
+        let mut channel = channel_reading(vec![data]);
+
+        assert_eq!(
+            channel.recv::<SetupMessage>().unwrap(),
+            SetupMessage::AssignPlayer(1)
+        );
+        assert_eq!(
+            channel.try_recv::<SetupMessage>().unwrap(),
+            Some(SetupMessage::Ready)
+        );
+        assert_eq!(channel.try_recv::<SetupMessage>().unwrap(), None);
+    }
+
+    #[test]
+    fn split_messages_wait_for_the_rest() {
+        let data = framed(&[SetupMessage::AssignPlayer(0)]);
+        let mut channel = channel_reading(vec![data[..2].to_vec()]);
+
+        assert_eq!(channel.try_recv::<SetupMessage>().unwrap(), None);
+        for byte in &data[2..data.len() - 1] {
+            channel.stream.reads.push_back(vec![*byte]);
+            assert_eq!(channel.try_recv::<SetupMessage>().unwrap(), None);
+        }
+        channel
+            .stream
+            .reads
+            .push_back(data[data.len() - 1..].to_vec());
+        assert_eq!(
+            channel.try_recv::<SetupMessage>().unwrap(),
+            Some(SetupMessage::AssignPlayer(0))
+        );
+    }
+
+    #[test]
+    fn blocking_reads_keep_partial_messages() {
+        let data = framed(&[SetupMessage::Ready]);
+        let mut channel = channel_reading(vec![data[..3].to_vec()]);
+
+        let error = channel.recv::<SetupMessage>().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+
+        channel.stream.reads.push_back(data[3..].to_vec());
+        assert_eq!(channel.recv::<SetupMessage>().unwrap(), SetupMessage::Ready);
+    }
+
+    #[test]
+    fn oversized_messages_are_invalid_data() {
+        let prefix = ((MAX_MESSAGE_SIZE + 1) as u32).to_be_bytes().to_vec();
+        let mut channel = channel_reading(vec![prefix]);
+
+        let error = channel.try_recv::<SetupMessage>().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn closed_streams_are_unexpected_eof() {
+        let mut channel = channel_reading(vec![Vec::new()]);
+
+        let error = channel.recv::<SetupMessage>().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
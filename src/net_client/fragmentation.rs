@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+// sequence (2 bytes), fragment index, fragment count
+pub const HEADER_SIZE: usize = 4;
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+fn write_header(sequence: u16, index: u8, count: u8) -> [u8; HEADER_SIZE] {
+    let sequence = sequence.to_be_bytes();
+    [sequence[0], sequence[1], index, count]
+}
+
+fn read_header(datagram: &[u8]) -> io::Result<(u16, u8, u8)> {
+    if datagram.len() < HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "recieved a datagram too short to hold a fragment header",
+        ));
+    }
+    let (sequence, index, count) = (
+        u16::from_be_bytes([datagram[0], datagram[1]]),
+        datagram[2],
+        datagram[3],
+    );
+    if index >= count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("fragment {} is outside of its {} fragments", index, count),
+        ));
+    }
+    Ok((sequence, index, count))
+}
+
+// the largest packet that fits in MAX_FRAGMENTS datagrams of mtu bytes
+pub fn max_packet_size(mtu: usize) -> usize {
+    (mtu - HEADER_SIZE) * MAX_FRAGMENTS
+}
+
+// splits packets into datagrams no bigger than the mtu, every datagram gets a header,
+// even if the packet fits in one, so the other side never has to guess
+#[derive(Debug, Default)]
+pub struct Fragmenter {
+    next_sequence: u16,
+}
+
+impl Fragmenter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fragment(&mut self, packet: &[u8], mtu: usize) -> io::Result<Vec<Vec<u8>>> {
+        if packet.len() > max_packet_size(mtu) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "packet of {} bytes is over the maximum of {} bytes for an mtu of {}",
+                    packet.len(),
+                    max_packet_size(mtu),
+                    mtu
+                ),
+            ));
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        // an empty packet still needs a datagram to arrive as
+        let chunks: Vec<_> = if packet.is_empty() {
+            vec![packet]
+        } else {
+            packet.chunks(mtu - HEADER_SIZE).collect()
+        };
+        let count = chunks.len() as u8;
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut datagram = Vec::with_capacity(HEADER_SIZE + chunk.len());
+                datagram.extend_from_slice(&write_header(sequence, index as u8, count));
+                datagram.extend_from_slice(chunk);
+                datagram
+            })
+            .collect())
+    }
+}
+
+struct PartialPacket {
+    started_at: Instant,
+    fragments: Vec<Option<Vec<u8>>>,
+    remaining: usize,
+}
+
+// puts fragments back together, a packet missing a fragment for longer than the timeout
+// is thrown out, the netcode resends anything that matters anyway
+pub struct Reassembler {
+    timeout: Duration,
+    partial_packets: HashMap<u16, PartialPacket>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            partial_packets: HashMap::new(),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    pub fn set_timeout(&mut self, value: Duration) {
+        self.timeout = value;
+    }
+    pub fn pending_packets(&self) -> usize {
+        self.partial_packets.len()
+    }
+
+    pub fn drop_expired(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.partial_packets
+            .retain(|_, partial| now.duration_since(partial.started_at) < timeout);
+    }
+
+    // returns the whole packet once its last fragment arrives
+    pub fn insert(&mut self, datagram: &[u8], now: Instant) -> io::Result<Option<Vec<u8>>> {
+        let (sequence, index, count) = read_header(datagram)?;
+        let data = &datagram[HEADER_SIZE..];
+        if count == 1 {
+            return Ok(Some(data.to_vec()));
+        }
+
+        self.drop_expired(now);
+        let partial = self
+            .partial_packets
+            .entry(sequence)
+            .or_insert_with(|| PartialPacket {
+                started_at: now,
+                fragments: vec![None; count as usize],
+                remaining: count as usize,
+            });
+        if partial.fragments.len() != count as usize {
+            // the sequence wrapped around onto a packet that never finished
+            *partial = PartialPacket {
+                started_at: now,
+                fragments: vec![None; count as usize],
+                remaining: count as usize,
+            };
+        }
+
+        let fragment = &mut partial.fragments[index as usize];
+        if fragment.is_none() {
+            *fragment = Some(data.to_vec());
+            partial.remaining -= 1;
+        }
+
+        if partial.remaining == 0 {
+            let partial = self.partial_packets.remove(&sequence).unwrap();
+            Ok(Some(
+                partial.fragments.into_iter().flatten().flatten().collect(),
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTU: usize = 16;
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn packet(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| idx as u8).collect()
+    }
+
+    #[test]
+    fn small_packets_are_one_datagram() {
+        let mut fragmenter = Fragmenter::new();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+
+        let datagrams = fragmenter.fragment(&packet(12), MTU).unwrap();
+
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(
+            reassembler.insert(&datagrams[0], Instant::now()).unwrap(),
+            Some(packet(12))
+        );
+    }
+
+    #[test]
+    fn reassembles_out_of_order_and_duplicated_fragments() {
+        let mut fragmenter = Fragmenter::new();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let now = Instant::now();
+
+        let mut datagrams = fragmenter.fragment(&packet(100), MTU).unwrap();
+        assert_eq!(datagrams.len(), 9);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= MTU));
+        datagrams.reverse();
+        let duplicate = datagrams[3].clone();
+        datagrams.insert(5, duplicate);
+
+        let (last, rest) = datagrams.split_last().unwrap();
+        for datagram in rest {
+            assert_eq!(reassembler.insert(datagram, now).unwrap(), None);
+        }
+        assert_eq!(reassembler.insert(last, now).unwrap(), Some(packet(100)));
+        assert_eq!(reassembler.pending_packets(), 0);
+    }
+
+    #[test]
+    fn interleaved_packets_reassemble_separately() {
+        let mut fragmenter = Fragmenter::new();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let now = Instant::now();
+
+        let first = fragmenter.fragment(&packet(30), MTU).unwrap();
+        let second = fragmenter.fragment(&packet(40), MTU).unwrap();
+
+        assert_eq!(reassembler.insert(&first[0], now).unwrap(), None);
+        assert_eq!(reassembler.insert(&second[0], now).unwrap(), None);
+        assert_eq!(reassembler.insert(&first[1], now).unwrap(), None);
+        assert_eq!(reassembler.insert(&second[1], now).unwrap(), None);
+        assert_eq!(
+            reassembler.insert(&first[2], now).unwrap(),
+            Some(packet(30))
+        );
+        assert_eq!(reassembler.insert(&second[2], now).unwrap(), None);
+        assert_eq!(
+            reassembler.insert(&second[3], now).unwrap(),
+            Some(packet(40))
+        );
+    }
+
+    #[test]
+    fn incomplete_packets_time_out() {
+        let mut fragmenter = Fragmenter::new();
+        let mut reassembler = Reassembler::new(TIMEOUT);
+        let now = Instant::now();
+
+        let datagrams = fragmenter.fragment(&packet(30), MTU).unwrap();
+        reassembler.insert(&datagrams[0], now).unwrap();
+        reassembler.insert(&datagrams[1], now).unwrap();
+
+        reassembler.drop_expired(now + TIMEOUT);
+        assert_eq!(reassembler.pending_packets(), 0);
+        // the last fragment on its own isn't a packet anymore
+        assert_eq!(
+            reassembler.insert(&datagrams[2], now + TIMEOUT).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn oversized_packets_are_rejected() {
+        let mut fragmenter = Fragmenter::new();
+
+        let error = fragmenter
+            .fragment(&packet(max_packet_size(MTU) + 1), MTU)
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(fragmenter
+            .fragment(&packet(max_packet_size(MTU)), MTU)
+            .is_ok());
+    }
+
+    #[test]
+    fn malformed_datagrams_are_invalid_data() {
+        let mut reassembler = Reassembler::new(TIMEOUT);
+
+        let short = reassembler.insert(&[0, 1], Instant::now()).unwrap_err();
+        let bad_index = reassembler
+            .insert(&[0, 1, 3, 3, 0], Instant::now())
+            .unwrap_err();
+
+        assert_eq!(short.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(bad_index.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,408 @@
+use super::{ControlChannel, SetupMessage};
+use crate::netcode::NetInput;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+// bump this whenever packets or setup messages change
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// both peers have to run with the same settings, or they'll drift apart
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetcodeSettings {
+    pub input_delay: usize,
+    pub allowed_rollback: usize,
+    // frames per second
+    pub tick_rate: u32,
+}
+
+// everything a peer has to agree on before playing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub input_layout: u64,
+    // should change with every build that changes the simulation
+    pub game_id: String,
+    pub settings: NetcodeSettings,
+}
+
+impl Hello {
+    pub fn new<Input: NetInput>(game_id: impl Into<String>, settings: NetcodeSettings) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            input_layout: Input::layout_hash(),
+            game_id: game_id.into(),
+            settings,
+        }
+    }
+
+    // expected is always ours, found is always theirs
+    pub fn check(&self, remote: &Hello) -> Result<(), RejectReason> {
+        if self.protocol_version != remote.protocol_version {
+            Err(RejectReason::ProtocolVersion {
+                expected: self.protocol_version,
+                found: remote.protocol_version,
+            })
+        } else if self.input_layout != remote.input_layout {
+            Err(RejectReason::InputLayout {
+                expected: self.input_layout,
+                found: remote.input_layout,
+            })
+        } else if self.game_id != remote.game_id {
+            Err(RejectReason::GameId {
+                expected: self.game_id.clone(),
+                found: remote.game_id.clone(),
+            })
+        } else if self.settings != remote.settings {
+            Err(RejectReason::Settings {
+                expected: self.settings,
+                found: remote.settings,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    ProtocolVersion {
+        expected: u32,
+        found: u32,
+    },
+    InputLayout {
+        expected: u64,
+        found: u64,
+    },
+    GameId {
+        expected: String,
+        found: String,
+    },
+    Settings {
+        expected: NetcodeSettings,
+        found: NetcodeSettings,
+    },
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectReason::ProtocolVersion { expected, found } => {
+                write!(f, "protocol version {} doesn't match {}", found, expected)
+            }
+            RejectReason::InputLayout { expected, found } => write!(
+                f,
+                "input layout {:016x} doesn't match {:016x}",
+                found, expected
+            ),
+            RejectReason::GameId { expected, found } => {
+                write!(f, "game {:?} doesn't match {:?}", found, expected)
+            }
+            RejectReason::Settings { expected, found } => {
+                write!(f, "settings {:?} don't match {:?}", found, expected)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(io::Error),
+    // we turned the peer away
+    Rejected(RejectReason),
+    // the peer turned us away
+    RejectedByPeer(RejectReason),
+    UnexpectedMessage(SetupMessage),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Io(error) => write!(f, "control channel error: {}", error),
+            HandshakeError::Rejected(reason) => write!(f, "rejected peer: {}", reason),
+            HandshakeError::RejectedByPeer(reason) => {
+                write!(f, "peer rejected us, their {}", reason)
+            }
+            HandshakeError::UnexpectedMessage(message) => {
+                write!(f, "unexpected setup message {:?}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<io::Error> for HandshakeError {
+    fn from(error: io::Error) -> Self {
+        HandshakeError::Io(error)
+    }
+}
+
+// what both sides agreed on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub remote: Hello,
+    // the index of the player we control
+    pub local_player: usize,
+}
+
+fn check_remote<S: Read + Write>(
+    channel: &mut ControlChannel<S>,
+    local: &Hello,
+    remote: &Hello,
+) -> Result<(), HandshakeError> {
+    if let Err(reason) = local.check(remote) {
+        // let them know why, so they don't just see the connection drop,
+        // if they already hung up the reason is still what matters
+        channel.send(&SetupMessage::Reject(reason.clone())).ok();
+        return Err(HandshakeError::Rejected(reason));
+    }
+    Ok(())
+}
+
+// the client says hello first, and the host either rejects it or answers with its own hello,
+// followed by the player the client controls, the client acks that with ready
+pub fn host<S: Read + Write>(
+    channel: &mut ControlChannel<S>,
+    local: &Hello,
+    local_player: usize,
+    remote_player: usize,
+) -> Result<Handshake, HandshakeError> {
+    let remote = match channel.recv()? {
+        SetupMessage::Hello(remote) => remote,
+        message => return Err(HandshakeError::UnexpectedMessage(message)),
+    };
+    check_remote(channel, local, &remote)?;
+
+    channel.send(&SetupMessage::Hello(local.clone()))?;
+    channel.send(&SetupMessage::AssignPlayer(remote_player))?;
+    match channel.recv()? {
+        SetupMessage::Ready => Ok(Handshake {
+            remote,
+            local_player,
+        }),
+        SetupMessage::Reject(reason) => Err(HandshakeError::RejectedByPeer(reason)),
+        message => Err(HandshakeError::UnexpectedMessage(message)),
+    }
+}
+
+pub fn connect<S: Read + Write>(
+    channel: &mut ControlChannel<S>,
+    local: &Hello,
+) -> Result<Handshake, HandshakeError> {
+    channel.send(&SetupMessage::Hello(local.clone()))?;
+    let remote = match channel.recv()? {
+        SetupMessage::Hello(remote) => remote,
+        SetupMessage::Reject(reason) => return Err(HandshakeError::RejectedByPeer(reason)),
+        message => return Err(HandshakeError::UnexpectedMessage(message)),
+    };
+    check_remote(channel, local, &remote)?;
+
+    let local_player = match channel.recv()? {
+        SetupMessage::AssignPlayer(index) => index,
+        message => return Err(HandshakeError::UnexpectedMessage(message)),
+    };
+    channel.send(&SetupMessage::Ready)?;
+    Ok(Handshake {
+        remote,
+        local_player,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netcode::{BitReader, BitWriter};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    // one end of an in memory stream, reads block until the other end writes
+    struct Pipe {
+        incoming: Receiver<Vec<u8>>,
+        outgoing: Sender<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Pipe {
+        fn pair() -> (Pipe, Pipe) {
+            let (first_send, first_recv) = channel();
+            let (second_send, second_recv) = channel();
+            (
+                Pipe {
+                    incoming: first_recv,
+                    outgoing: second_send,
+                    pending: Vec::new(),
+                },
+                Pipe {
+                    incoming: second_recv,
+                    outgoing: first_send,
+                    pending: Vec::new(),
+                },
+            )
+        }
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.incoming.recv() {
+                    Ok(data) => self.pending = data,
+                    // the other end hung up
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = self.pending.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.pending[..len]);
+            self.pending.drain(..len);
+            Ok(len)
+        }
+    }
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct FirstInput(i8);
+    impl NetInput for FirstInput {}
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct SecondInput(i32);
+    impl NetInput for SecondInput {}
+
+    // same type, but the encoding changed
+    #[derive(Serialize, Deserialize, Default)]
+    struct PackedFirstInput(i8);
+    impl NetInput for PackedFirstInput {
+        fn encode_bits(&self, writer: &mut BitWriter) {
+            writer.write_bits(self.0 as u8 as u64, 2);
+        }
+        fn decode_bits(reader: &mut BitReader<'_>) -> Option<Self> {
+            reader.read_bits(2).map(|bits| PackedFirstInput(bits as i8))
+        }
+    }
+
+    // same encoding, but it means something else now
+    #[derive(Serialize, Deserialize, Default)]
+    struct VersionedFirstInput(i8);
+    impl NetInput for VersionedFirstInput {
+        const LAYOUT_VERSION: u32 = 1;
+    }
+
+    const SETTINGS: NetcodeSettings = NetcodeSettings {
+        input_delay: 1,
+        allowed_rollback: 9,
+        tick_rate: 60,
+    };
+
+    fn run(
+        host_hello: Hello,
+        client_hello: Hello,
+    ) -> (
+        Result<Handshake, HandshakeError>,
+        Result<Handshake, HandshakeError>,
+    ) {
+        let (host_pipe, client_pipe) = Pipe::pair();
+        let host_thread = thread::spawn(move || {
+            let mut channel = ControlChannel::new(host_pipe);
+            host(&mut channel, &host_hello, 1, 0)
+        });
+        let client = connect(&mut ControlChannel::new(client_pipe), &client_hello);
+        (host_thread.join().unwrap(), client)
+    }
+
+    #[test]
+    fn compatible_peers_get_their_players() {
+        let hello = Hello::new::<FirstInput>("test 1.0", SETTINGS);
+
+        let (host, client) = run(hello.clone(), hello.clone());
+
+        let host = host.unwrap();
+        let client = client.unwrap();
+        assert_eq!(host.local_player, 1);
+        assert_eq!(client.local_player, 0);
+        assert_eq!(host.remote, hello);
+        assert_eq!(client.remote, hello);
+    }
+
+    #[test]
+    fn different_protocol_versions_are_rejected() {
+        let hello = Hello::new::<FirstInput>("test 1.0", SETTINGS);
+        let old_hello = Hello {
+            protocol_version: PROTOCOL_VERSION - 1,
+            ..hello.clone()
+        };
+
+        let (host, client) = run(hello, old_hello);
+
+        let reason = RejectReason::ProtocolVersion {
+            expected: PROTOCOL_VERSION,
+            found: PROTOCOL_VERSION - 1,
+        };
+        match (host, client) {
+            (Err(HandshakeError::Rejected(host)), Err(HandshakeError::RejectedByPeer(client))) => {
+                assert_eq!(host, reason);
+                assert_eq!(client, reason);
+            }
+            result => panic!("expected a rejection, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn different_inputs_are_rejected() {
+        assert_ne!(FirstInput::layout_hash(), SecondInput::layout_hash());
+        assert_ne!(FirstInput::layout_hash(), PackedFirstInput::layout_hash());
+        assert_ne!(
+            FirstInput::layout_hash(),
+            VersionedFirstInput::layout_hash()
+        );
+        assert_eq!(FirstInput::layout_hash(), FirstInput::layout_hash());
+
+        let (host, client) = run(
+            Hello::new::<FirstInput>("test 1.0", SETTINGS),
+            Hello::new::<SecondInput>("test 1.0", SETTINGS),
+        );
+
+        assert!(matches!(
+            host,
+            Err(HandshakeError::Rejected(RejectReason::InputLayout { .. }))
+        ));
+        assert!(matches!(
+            client,
+            Err(HandshakeError::RejectedByPeer(
+                RejectReason::InputLayout { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn different_games_and_settings_are_rejected() {
+        let hello = Hello::new::<FirstInput>("test 1.0", SETTINGS);
+
+        let (host, _) = run(
+            hello.clone(),
+            Hello::new::<FirstInput>("test 1.1", SETTINGS),
+        );
+        assert!(matches!(
+            host,
+            Err(HandshakeError::Rejected(RejectReason::GameId { .. }))
+        ));
+
+        let slower = NetcodeSettings {
+            tick_rate: 30,
+            ..SETTINGS
+        };
+        let (host, _) = run(hello, Hello::new::<FirstInput>("test 1.0", slower));
+        assert!(matches!(
+            host,
+            Err(HandshakeError::Rejected(RejectReason::Settings { .. }))
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+use super::Transport;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct InFlight {
+    deliver_at: Duration,
+    from: usize,
+    to: usize,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct HubState {
+    now: Duration,
+    latency: Duration,
+    // every nth packet sent gets dropped
+    drop_every: Option<usize>,
+    sent: usize,
+    in_flight: Vec<InFlight>,
+    inboxes: Vec<VecDeque<(usize, Vec<u8>)>>,
+}
+
+impl HubState {
+    fn deliver(&mut self) {
+        let now = self.now;
+        let (arrived, in_flight) = self
+            .in_flight
+            .drain(..)
+            .partition::<Vec<_>, _>(|packet| packet.deliver_at <= now);
+        self.in_flight = in_flight;
+        for packet in arrived {
+            self.inboxes[packet.to].push_back((packet.from, packet.data));
+        }
+    }
+}
+
+// connects any number of in memory transports, time only moves when advance is called,
+// so everything sent through it happens the same way every run
+#[derive(Clone, Default)]
+pub struct MemoryHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+impl MemoryHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_peer(&self) -> MemoryTransport {
+        let mut state = self.state.lock().unwrap();
+        state.inboxes.push(VecDeque::new());
+        MemoryTransport {
+            id: state.inboxes.len() - 1,
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+    // follows the hub's time instead of the wall clock, see NetcodeClient::set_clock
+    pub fn clock(&self) -> impl Fn() -> Instant {
+        let start = Instant::now();
+        let hub = self.clone();
+        move || start + hub.now()
+    }
+    pub fn latency(&self) -> Duration {
+        self.state.lock().unwrap().latency
+    }
+    pub fn set_latency(&self, value: Duration) {
+        self.state.lock().unwrap().latency = value;
+    }
+    pub fn drop_every(&self) -> Option<usize> {
+        self.state.lock().unwrap().drop_every
+    }
+    pub fn set_drop_every(&self, value: Option<usize>) {
+        self.state.lock().unwrap().drop_every = value.filter(|every| *every > 0);
+    }
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight.len()
+    }
+
+    // moves the clock forward, delivering anything that arrives by then
+    pub fn advance(&self, time: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += time;
+        state.deliver();
+    }
+}
+
+pub struct MemoryTransport {
+    id: usize,
+    state: Arc<Mutex<HubState>>,
+}
+
+impl MemoryTransport {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Peer = usize;
+
+    fn send_to(&mut self, peer: usize, data: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if peer >= state.inboxes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no peer with that id on the hub",
+            ));
+        }
+        state.sent += 1;
+        if let Some(every) = state.drop_every {
+            if state.sent % every == 0 {
+                return Ok(());
+            }
+        }
+        let deliver_at = state.now + state.latency;
+        state.in_flight.push(InFlight {
+            deliver_at,
+            from: self.id,
+            to: peer,
+            data: data.to_vec(),
+        });
+        state.deliver();
+        Ok(())
+    }
+    fn recv_from(&mut self) -> io::Result<Option<(usize, Vec<u8>)>> {
+        Ok(self.state.lock().unwrap().inboxes[self.id].pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn packets_arrive_after_the_latency() {
+        let hub = MemoryHub::new();
+        hub.set_latency(FRAME * 2);
+        let mut first = hub.add_peer();
+        let mut second = hub.add_peer();
+
+        first.send_to(second.id(), &[1, 2]).unwrap();
+        assert_eq!(hub.latency(), FRAME * 2);
+        assert_eq!(hub.in_flight(), 1);
+        hub.advance(FRAME);
+        assert_eq!(second.recv_from().unwrap(), None);
+
+        hub.advance(FRAME);
+        assert_eq!(hub.in_flight(), 0);
+        assert_eq!(hub.now(), FRAME * 2);
+        assert_eq!(second.recv_from().unwrap(), Some((first.id(), vec![1, 2])));
+        assert_eq!(first.recv_from().unwrap(), None);
+    }
+
+    #[test]
+    fn every_nth_packet_gets_dropped() {
+        let hub = MemoryHub::new();
+        hub.set_drop_every(Some(3));
+        let mut first = hub.add_peer();
+        let mut second = hub.add_peer();
+
+        for packet in 0..6 {
+            first.send_to(second.id(), &[packet]).unwrap();
+        }
+
+        let received: Vec<_> = std::iter::from_fn(|| second.recv_from().unwrap())
+            .map(|(_, data)| data[0])
+            .collect();
+        assert_eq!(received, vec![0, 1, 3, 4]);
+        // nobody drops every 0th packet
+        hub.set_drop_every(Some(0));
+        assert_eq!(hub.drop_every(), None);
+    }
+
+    #[test]
+    fn unknown_peers_cant_be_sent_to() {
+        let hub = MemoryHub::new();
+        let mut first = hub.add_peer();
+
+        let error = first.send_to(first.id() + 1, &[1]).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,17 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+
+// anything that can move packets of bytes between peers without blocking,
+// packets are allowed to be dropped, duplicated, or reordered, just like udp
+pub trait Transport {
+    type Peer: Copy + Eq + Hash + Debug;
+
+    fn send_to(&mut self, peer: Self::Peer, data: &[u8]) -> io::Result<()>;
+    // Ok(None) once there's nothing left to receive
+    fn recv_from(&mut self) -> io::Result<Option<(Self::Peer, Vec<u8>)>>;
+    // called every update, for transports that need to do work outside of sending and receiving
+    fn poll(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,15 +1,43 @@
-use crate::input_history::{LocalHistory, NetworkedHistory, PredictionResult};
+mod net_input;
+mod netcode_error;
+mod netcode_event;
+mod netcode_request;
+mod replay;
+mod rollback_stats;
+mod save_state_store;
+mod spectator_client;
+mod time_sync;
+mod wire_frame;
+
+pub use net_input::{fnv1a, BitReader, BitWriter, NetInput, PackedInputs, FNV_OFFSET};
+pub use netcode_error::NetcodeError;
+pub use netcode_event::NetcodeEvent;
+pub use netcode_request::{NetcodeRequest, UpdateRequests};
+pub use replay::{Replay, ReplayPlayer, ReplaySettings};
+pub use rollback_stats::{PredictionStats, RollbackStats};
+pub use save_state_store::{RingBufferStore, SaveStateStore};
+pub use wire_frame::WireFrame;
+
+use time_sync::TimeSync;
+
+pub use crate::input_history::{DecayToNeutral, Neutral, PredictionStrategy, RepeatLast};
+
+pub use crate::input_history::InputStatus;
+
+use crate::input_history::{InputHistory, LocalHistory, NetworkedHistory, PredictionResult};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
 
-// TODO, consider parameterizing the size of current_frame to not waste bytes on the fact that its
-// at least 4 bytes when 18 minutes of 60 FPS gameplay only needs a u16 (2 bytes)
 // TODO, add a bunch of functions to perform syncing of the clients, but not pass input back and forth
-// TODO, make everything return a Result, taht way users can decide whether or not to handle errorsalso
 
 // TODO, create getters/setters for all the public properties
 
+// events nobody polled get thrown out past this, oldest first
+pub const MAX_QUEUED_EVENTS: usize = 256;
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 enum PlayerType {
     Local,
@@ -25,54 +53,228 @@ impl PlayerHandle {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct SpectatorHandle(usize);
+
+impl SpectatorHandle {
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected,
+    // we haven't heard from them in a bit, but they might come back
+    Interrupted,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    // update returns an error once anyone disconnects
+    EndSession,
+    // keep playing, with the disconnected player's inputs fixed to the default input
+    ContinueWithDefaultInput,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Connection {
+    last_received: Instant,
+    status: ConnectionStatus,
+    heard_from: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 struct PlayerInfo {
     player_type: PlayerType,
     id: PlayerHandle,
 }
 
+#[derive(Debug, Clone)]
 pub struct InputSet<'a, Input> {
+    pub frame: usize,
+    // true while rolling back, so things like sounds and particles can be skipped
+    pub is_resimulating: bool,
+    // the held inputs of every player in PlayerHandle order, ending with the input for this frame
     pub inputs: Vec<&'a [Input]>,
+    players: &'a [PlayerHandle],
+    statuses: Vec<InputStatus>,
+}
+
+impl<'a, Input> InputSet<'a, Input> {
+    pub fn players(&self) -> &[PlayerHandle] {
+        self.players
+    }
+    fn index(&self, player: PlayerHandle) -> Option<usize> {
+        self.players.iter().position(|handle| *handle == player)
+    }
+
+    pub fn get(&self, player: PlayerHandle) -> Option<&'a [Input]> {
+        self.index(player).map(|idx| self.inputs[idx])
+    }
+    // whether the player's input for this frame is predicted or confirmed
+    pub fn status(&self, player: PlayerHandle) -> Option<InputStatus> {
+        self.index(player).map(|idx| self.statuses[idx])
+    }
+    // nothing this frame was predicted, so it won't be rolled back unless an earlier frame is
+    pub fn is_confirmed(&self) -> bool {
+        self.statuses
+            .iter()
+            .all(|status| *status == InputStatus::Confirmed)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound(
+    serialize = "Input: NetInput + PartialEq",
+    deserialize = "Input: NetInput + Clone"
+))]
 pub enum Packet<Input> {
-    Inputs(PlayerHandle, usize, usize, Vec<Input>),
-    Request(usize),
-    Provide(Vec<(PlayerHandle, usize, Vec<Input>)>),
+    // player, frame it was sent on, sender's frame advantage, frame the inputs start on, inputs,
+    // and the sender's acks for everyone else's inputs, so they don't need a packet of their own
+    Inputs(
+        PlayerHandle,
+        WireFrame,
+        i32,
+        WireFrame,
+        PackedInputs<Input>,
+        Vec<(PlayerHandle, WireFrame)>,
+    ),
+    Request(WireFrame),
+    Provide(Vec<(PlayerHandle, WireFrame, PackedInputs<Input>)>),
+    // sender's first local player, frame, the sender's checksum for that frame
+    Checksum(PlayerHandle, WireFrame, u64),
+    // player, the next frame of their inputs we need
+    InputAck(PlayerHandle, WireFrame),
+    // confirmed inputs starting at the given frame, one set of inputs per player, in player order
+    Spectate(WireFrame, Vec<PackedInputs<Input>>),
+    // the next frame the spectator needs
+    SpectatorAck(WireFrame),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    // the peer whose checksum didn't match ours
+    pub player: PlayerHandle,
+    pub frame: usize,
+    pub local_checksum: u64,
+    pub remote_checksum: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncTestFailure {
+    pub frame: usize,
+    pub original_checksum: u64,
+    pub resimulated_checksum: u64,
 }
-pub struct NetcodeClient<Input, GameState> {
+
+pub struct NetcodeClient<Input, GameState, Store = HashMap<usize, GameState>> {
     local_players: HashMap<PlayerHandle, LocalHistory<Input>>,
     net_players: HashMap<PlayerHandle, NetworkedHistory<Input>>,
+    prediction_strategies: HashMap<PlayerHandle, Box<dyn PredictionStrategy<Input>>>,
     current_frame: usize,
     held_input_count: usize,
-    skip_frames: usize,
-    saved_rollback_states: HashMap<usize, GameState>,
-    rollback_to: Option<(usize, GameState)>,
+    time_sync: TimeSync,
+    time_sync_interval: usize,
+    next_time_sync_frame: usize,
+    slowdown: f32,
+    slowdown_rate: f32,
+    automatic_time_sync: bool,
+    // every frame that could still be rolled back to has a state saved from right before it ran
+    saved_rollback_states: Store,
+    saved_state: PhantomData<GameState>,
+    rollback_to: Option<usize>,
     players: Vec<PlayerInfo>,
+    // the same order as players, so InputSets can borrow it
+    handles: Vec<PlayerHandle>,
     network_delay: HashMap<PlayerHandle, usize>,
+    connections: HashMap<PlayerHandle, Connection>,
+    interrupt_timeout: Duration,
+    disconnect_timeout: Duration,
+    disconnect_policy: DisconnectPolicy,
+    clock: Box<dyn Fn() -> Instant>,
     input_delay: usize,
     allowed_rollback: usize,
     packet_buffer_size: usize,
+    acked_inputs: HashMap<PlayerHandle, usize>,
+    checksum_interval: usize,
+    local_checksums: HashMap<usize, u64>,
+    // every peer's checksums are kept apart, so one can't cover for another
+    remote_checksums: HashMap<(PlayerHandle, usize), u64>,
+    last_sent_checksum: Option<usize>,
+    desync: Option<Desync>,
+    sync_test: Option<usize>,
+    sync_test_checksums: HashMap<usize, u64>,
+    sync_test_failure: Option<SyncTestFailure>,
+    stats: RollbackStats,
+    spectators: HashMap<SpectatorHandle, usize>,
+    next_spectator: usize,
+    // every input history holds everything from this frame on
+    synchronized: bool,
+    events: VecDeque<NetcodeEvent>,
+    // the players we're stalled on, so WaitingForPlayer only goes out once per stall
+    waiting_for: Vec<PlayerHandle>,
+    recording: Option<Replay<Input>>,
 }
 
 impl<Input: Clone + Default + PartialEq + std::fmt::Debug, GameState: std::fmt::Debug>
     NetcodeClient<Input, GameState>
 {
     pub fn new(held_input_count: usize) -> Self {
+        Self::with_save_state_store(held_input_count, HashMap::new())
+    }
+}
+
+impl<
+        Input: Clone + Default + PartialEq + std::fmt::Debug,
+        GameState: std::fmt::Debug,
+        Store: SaveStateStore<GameState>,
+    > NetcodeClient<Input, GameState, Store>
+{
+    pub fn with_save_state_store(held_input_count: usize, store: Store) -> Self {
         Self {
             local_players: HashMap::new(),
             net_players: HashMap::new(),
+            prediction_strategies: HashMap::new(),
             current_frame: 0,
             held_input_count,
-            skip_frames: 0,
+            time_sync: TimeSync::new(40),
+            time_sync_interval: 30,
+            next_time_sync_frame: 0,
+            slowdown: 0.0,
+            slowdown_rate: 0.0,
+            automatic_time_sync: true,
             packet_buffer_size: 10,
+            acked_inputs: HashMap::new(),
             input_delay: 1,
             network_delay: HashMap::new(),
-            saved_rollback_states: HashMap::new(),
+            connections: HashMap::new(),
+            interrupt_timeout: Duration::from_millis(750),
+            disconnect_timeout: Duration::from_secs(5),
+            disconnect_policy: DisconnectPolicy::EndSession,
+            clock: Box::new(Instant::now),
+            saved_rollback_states: store,
+            saved_state: PhantomData,
             allowed_rollback: 9,
             rollback_to: None,
             players: Vec::new(),
+            handles: Vec::new(),
+            checksum_interval: 10,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            last_sent_checksum: None,
+            desync: None,
+            sync_test: None,
+            sync_test_checksums: HashMap::new(),
+            sync_test_failure: None,
+            stats: RollbackStats::default(),
+            spectators: HashMap::new(),
+            next_spectator: 0,
+            synchronized: false,
+            events: VecDeque::new(),
+            waiting_for: Vec::new(),
+            recording: None,
         }
     }
 
@@ -87,6 +289,14 @@ impl<Input: Clone + Default + PartialEq + std::fmt::Debug, GameState: std::fmt::
     }
     pub fn set_allowed_rollback(&mut self, value: usize) {
         self.allowed_rollback = value;
+
+        let capacity = self.history_capacity();
+        for local_player in self.local_players.values_mut() {
+            local_player.set_capacity(capacity);
+        }
+        for net_player in self.net_players.values_mut() {
+            net_player.set_capacity(capacity);
+        }
     }
     pub fn packet_buffer_size(&self) -> usize {
         self.packet_buffer_size
@@ -95,21 +305,127 @@ impl<Input: Clone + Default + PartialEq + std::fmt::Debug, GameState: std::fmt::
         self.packet_buffer_size = value;
     }
 
-    pub fn get_network_delay(&self, player: PlayerHandle) -> usize {
-        assert!(
-            self.players[player.id()].player_type == PlayerType::Net,
-            "Must handle networked input for a networked player."
-        );
+    // the first frame where our confirmed checksum didn't match the one our peer sent us
+    pub fn desync(&self) -> Option<Desync> {
+        self.desync
+    }
 
-        self.network_delay[&player]
+    pub fn sync_test(&self) -> Option<usize> {
+        self.sync_test
     }
-    pub fn set_network_delay(&mut self, value: usize, player: PlayerHandle) {
-        assert!(
-            self.players[player.id()].player_type == PlayerType::Net,
-            "Must handle networked input for a networked player."
-        );
+    // every frame, rolls back check_distance frames and resimulates them, comparing checksums
+    // against the first simulation, only usable without net players
+    pub fn set_sync_test(&mut self, check_distance: Option<usize>) -> Result<(), NetcodeError> {
+        if !self.net_players.is_empty() {
+            return Err(NetcodeError::SyncTestWithNetPlayers);
+        }
+        if check_distance == Some(0) {
+            return Err(NetcodeError::InvalidSyncTestDistance);
+        }
+        self.sync_test = check_distance;
+        Ok(())
+    }
+    // the first frame where resimulating didn't produce the same checksum as the first simulation
+    pub fn sync_test_failure(&self) -> Option<SyncTestFailure> {
+        self.sync_test_failure
+    }
+
+    // how many frames ahead of our peers we are, if this is positive, we should be slowing down
+    pub fn time_sync_recommendation(&self) -> f32 {
+        self.time_sync.recommended_frame_delay()
+    }
+    pub fn automatic_time_sync(&self) -> bool {
+        self.automatic_time_sync
+    }
+    // turn this off to pace ticks yourself using time_sync_recommendation instead of having
+    // update skip frames
+    pub fn set_automatic_time_sync(&mut self, value: bool) {
+        self.automatic_time_sync = value;
+        self.slowdown = 0.0;
+        self.slowdown_rate = 0.0;
+    }
+    pub fn time_sync_interval(&self) -> usize {
+        self.time_sync_interval
+    }
+    // the recommended slowdown gets spread out over this many frames, and recalculated after them
+    pub fn set_time_sync_interval(&mut self, value: usize) {
+        self.time_sync_interval = value.max(1);
+    }
+
+    // polling is optional, only the newest MAX_QUEUED_EVENTS are kept
+    pub fn poll_events(&mut self) -> impl Iterator<Item = NetcodeEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    pub fn stats(&self) -> &RollbackStats {
+        &self.stats
+    }
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    pub fn get_network_delay(&self, player: PlayerHandle) -> Result<usize, NetcodeError> {
+        self.check_net_player(player)?;
+
+        Ok(self.network_delay[&player])
+    }
+    pub fn set_network_delay(
+        &mut self,
+        value: usize,
+        player: PlayerHandle,
+    ) -> Result<(), NetcodeError> {
+        self.check_net_player(player)?;
 
         self.network_delay.insert(player, value);
+        Ok(())
+    }
+
+    pub fn set_prediction_strategy<S: PredictionStrategy<Input> + 'static>(
+        &mut self,
+        player: PlayerHandle,
+        strategy: S,
+    ) -> Result<(), NetcodeError> {
+        self.check_net_player(player)?;
+
+        self.prediction_strategies
+            .insert(player, Box::new(strategy));
+        Ok(())
+    }
+
+    pub fn interrupt_timeout(&self) -> Duration {
+        self.interrupt_timeout
+    }
+    pub fn set_interrupt_timeout(&mut self, value: Duration) {
+        self.interrupt_timeout = value;
+    }
+    pub fn disconnect_timeout(&self) -> Duration {
+        self.disconnect_timeout
+    }
+    pub fn set_disconnect_timeout(&mut self, value: Duration) {
+        self.disconnect_timeout = value;
+    }
+    pub fn disconnect_policy(&self) -> DisconnectPolicy {
+        self.disconnect_policy
+    }
+    pub fn set_disconnect_policy(&mut self, value: DisconnectPolicy) {
+        self.disconnect_policy = value;
+    }
+    // where the timeouts get the time from, so they can follow something other than the wall clock,
+    // switching clocks restarts every timeout
+    pub fn set_clock<C: Fn() -> Instant + 'static>(&mut self, clock: C) {
+        let now = clock();
+        for connection in self.connections.values_mut() {
+            connection.last_received = now;
+        }
+        self.clock = Box::new(clock);
+    }
+    pub fn connection_status(
+        &self,
+        player: PlayerHandle,
+    ) -> Result<ConnectionStatus, NetcodeError> {
+        self.check_net_player(player)?;
+
+        Ok(self.connections[&player].status)
     }
 
     pub fn current_frame(&self) -> usize {
@@ -120,237 +436,874 @@ impl<Input: Clone + Default + PartialEq + std::fmt::Debug, GameState: std::fmt::
         self.current_frame + self.input_delay
     }
 
-    pub fn add_local_player(&mut self, index: usize) -> PlayerHandle {
+    // we hold onto held_input_count + allowed_rollback frames in the past, and leave the same
+    // amount of room for inputs that arrive ahead of the current frame
+    fn history_capacity(&self) -> usize {
+        2 * (self.held_input_count + self.allowed_rollback)
+    }
+
+    fn resolve_frame(&self, frame: WireFrame) -> Result<usize, NetcodeError> {
+        frame
+            .resolve(self.current_frame)
+            .ok_or(NetcodeError::FrameOutOfRange)
+    }
+
+    fn player_type(&self, player: PlayerHandle) -> Result<PlayerType, NetcodeError> {
+        self.players
+            .iter()
+            .find(|info| info.id == player)
+            .map(|info| info.player_type)
+            .ok_or(NetcodeError::UnknownPlayer(player))
+    }
+    fn check_local_player(&self, player: PlayerHandle) -> Result<(), NetcodeError> {
+        match self.player_type(player)? {
+            PlayerType::Local => Ok(()),
+            PlayerType::Net => Err(NetcodeError::NotLocalPlayer(player)),
+        }
+    }
+    fn check_net_player(&self, player: PlayerHandle) -> Result<(), NetcodeError> {
+        match self.player_type(player)? {
+            PlayerType::Net => Ok(()),
+            PlayerType::Local => Err(NetcodeError::NotNetPlayer(player)),
+        }
+    }
+
+    // the last frame we have this player's confirmed input for, along with every frame before it
+    pub fn last_confirmed_frame(
+        &self,
+        player: PlayerHandle,
+    ) -> Result<Option<usize>, NetcodeError> {
+        self.player_type(player)?;
+
+        Ok(self.history(player).next_unconfirmed_frame().checked_sub(1))
+    }
+
+    // every frame up to and including this one was simulated with everyone's confirmed input,
+    // so it'll never be rolled back, and anything that can't be undone is safe to commit
+    pub fn confirmed_frame(&self) -> Option<usize> {
+        self.earliest_unconfirmed_frame().checked_sub(1)
+    }
+
+    // the earliest frame that could still be resimulated
+    fn earliest_unconfirmed_frame(&self) -> usize {
+        let pending_rollback = self.rollback_to;
+        // sync testing rolls back check_distance frames every update
+        let sync_test_rollback = self
+            .sync_test
+            .map(|check_distance| (self.current_frame + 1).saturating_sub(check_distance));
+        self.players
+            .iter()
+            .map(|info| self.history(info.id).next_unconfirmed_frame())
+            .chain(pending_rollback)
+            .chain(sync_test_rollback)
+            .fold(self.current_frame, usize::min)
+    }
+
+    fn is_confirmed_through(&self, frame: usize) -> bool {
+        self.confirmed_frame()
+            .map(|confirmed| frame <= confirmed)
+            .unwrap_or(false)
+    }
+
+    // the oldest input anyone could still need
+    fn clean_target(&self, earliest_unconfirmed_frame: usize) -> usize {
+        // resimulating a frame needs held_input_count frames of input up to it
+        let target = (earliest_unconfirmed_frame + 1).saturating_sub(self.held_input_count);
+        // spectators still need everything they haven't acked, but only so far back,
+        // or a spectator that stopped acking would fill up every history
+        self.spectators
+            .values()
+            .map(|next_frame| (*next_frame).max(self.oldest_spectator_frame()))
+            .fold(target, usize::min)
+    }
+    // spectators behind this can't catch up anymore
+    fn oldest_spectator_frame(&self) -> usize {
+        self.current_frame
+            .saturating_sub(self.held_input_count + self.allowed_rollback)
+    }
+
+    fn check_checksums(&mut self) {
+        let mut confirmed: Vec<_> = self
+            .remote_checksums
+            .keys()
+            .filter(|(_, frame)| {
+                self.local_checksums.contains_key(frame) && self.is_confirmed_through(*frame)
+            })
+            .cloned()
+            .collect();
+        confirmed.sort_by_key(|(player, frame)| (*frame, player.id()));
+
+        for (player, frame) in confirmed {
+            let remote_checksum = self.remote_checksums.remove(&(player, frame)).unwrap();
+            let local_checksum = self.local_checksums[&frame];
+            if local_checksum != remote_checksum && self.desync.is_none() {
+                let desync = Desync {
+                    player,
+                    frame,
+                    local_checksum,
+                    remote_checksum,
+                };
+                self.desync = Some(desync);
+                self.events.push_back(NetcodeEvent::Desync(desync));
+            }
+        }
+    }
+
+    fn checksum_packet(&mut self) -> Option<Packet<Input>> {
+        // our peers tell us apart by our first local player
+        let sender = self
+            .players
+            .iter()
+            .find(|info| info.player_type == PlayerType::Local)?
+            .id;
+        let (frame, checksum) = self
+            .local_checksums
+            .iter()
+            .filter(|(frame, _)| {
+                self.last_sent_checksum
+                    .map(|sent| **frame > sent)
+                    .unwrap_or(true)
+                    && self.is_confirmed_through(**frame)
+            })
+            .max_by_key(|(frame, _)| **frame)
+            .map(|(frame, checksum)| (*frame, *checksum))?;
+
+        self.last_sent_checksum = Some(frame);
+        Some(Packet::Checksum(sender, WireFrame::new(frame), checksum))
+    }
+
+    pub fn add_local_player(&mut self, index: usize) -> Result<PlayerHandle, NetcodeError> {
+        self.check_not_recording()?;
         let handle = PlayerHandle(index);
+        if self.player_type(handle).is_ok() {
+            return Err(NetcodeError::PlayerAlreadyAdded(handle));
+        }
         let info: PlayerInfo = PlayerInfo {
             id: handle,
             player_type: PlayerType::Local,
         };
-        self.local_players.insert(handle, LocalHistory::new());
+        self.local_players
+            .insert(handle, LocalHistory::new(self.history_capacity()));
+        self.acked_inputs.insert(handle, 0);
         self.players.push(info);
         self.players
             .sort_by(|lhs, rhs| lhs.id.id().cmp(&rhs.id.id()));
+        self.handles = self.players.iter().map(|info| info.id).collect();
 
-        handle
+        Ok(handle)
     }
-    pub fn add_net_player(&mut self, index: usize) -> PlayerHandle {
+    pub fn add_net_player(&mut self, index: usize) -> Result<PlayerHandle, NetcodeError> {
+        if self.sync_test.is_some() {
+            return Err(NetcodeError::SyncTestWithNetPlayers);
+        }
+        self.check_not_recording()?;
         let handle = PlayerHandle(index);
+        if self.player_type(handle).is_ok() {
+            return Err(NetcodeError::PlayerAlreadyAdded(handle));
+        }
         let info: PlayerInfo = PlayerInfo {
             id: handle,
             player_type: PlayerType::Net,
         };
-        self.net_players.insert(handle, NetworkedHistory::new());
+        self.net_players
+            .insert(handle, NetworkedHistory::new(self.history_capacity()));
+        self.prediction_strategies
+            .insert(handle, Box::new(RepeatLast));
         self.players.push(info);
         self.players
             .sort_by(|lhs, rhs| lhs.id.id().cmp(&rhs.id.id()));
+        self.handles = self.players.iter().map(|info| info.id).collect();
         self.network_delay.insert(handle, 0);
+        self.connections.insert(
+            handle,
+            Connection {
+                last_received: (self.clock)(),
+                status: ConnectionStatus::Connected,
+                heard_from: false,
+            },
+        );
 
-        handle
+        Ok(handle)
     }
 
     pub fn handle_local_input(
         &mut self,
         data: Input,
         player: PlayerHandle,
-    ) -> Option<Packet<Input>> {
-        assert!(
-            self.players[player.id()].player_type == PlayerType::Local,
-            "Must add local input to a local player."
-        );
+    ) -> Result<Option<Packet<Input>>, NetcodeError> {
+        self.check_local_player(player)?;
+
         let delayed_current_frame = self.delayed_current_frame();
+        let acks = self.input_acks();
         let local_player = self.local_players.get_mut(&player).unwrap();
         if !local_player.has_input(delayed_current_frame) {
-            let input_frame = local_player.add_input(data);
-            let buffer_size = self.packet_buffer_size;
+            let input_frame = local_player
+                .add_input(data)
+                .map_err(|error| NetcodeError::from_history(error, player))?;
+            // only resend what hasn't been acked, up to the packet buffer size
+            let unacked = (input_frame + 1).saturating_sub(self.acked_inputs[&player]);
+            let buffer_size = unacked.max(1).min(self.packet_buffer_size);
 
-            let (range, data) = local_player.get_inputs(input_frame, buffer_size);
+            // we just added input_frame, so there's always a window
+            let window = local_player
+                .request_inputs(input_frame, buffer_size)
+                .unwrap();
 
-            Some(Packet::Inputs(
+            Ok(Some(Packet::Inputs(
                 player,
-                self.current_frame,
-                range.first,
-                data.iter().cloned().collect(),
-            ))
+                WireFrame::new(self.current_frame),
+                self.time_sync.local_advantage().round() as i32,
+                WireFrame::new(window.range.first),
+                PackedInputs(window.inputs.to_vec()),
+                acks,
+            )))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    pub fn handle_net_input(&mut self, frame: usize, input: Input, player: PlayerHandle) {
-        assert!(
-            self.players[player.id()].player_type == PlayerType::Net,
-            "Must handle networked input for a networked player."
-        );
+    // the next frame we need from every net player
+    fn input_acks(&self) -> Vec<(PlayerHandle, WireFrame)> {
+        self.net_players
+            .iter()
+            .map(|(handle, player)| (*handle, WireFrame::new(player.next_unconfirmed_frame())))
+            .collect()
+    }
+    fn handle_input_ack(
+        &mut self,
+        player_handle: PlayerHandle,
+        next_frame: WireFrame,
+    ) -> Result<(), NetcodeError> {
+        let next_frame = self.resolve_frame(next_frame)?;
+        // acks can arrive out of order, so only ever move forward
+        let acked = self.acked_inputs.get_mut(&player_handle).unwrap();
+        *acked = next_frame.max(*acked);
+        Ok(())
+    }
+
+    pub fn handle_net_input(
+        &mut self,
+        frame: usize,
+        input: Input,
+        player: PlayerHandle,
+    ) -> Result<(), NetcodeError> {
+        self.check_net_player(player)?;
+
+        // a wrong prediction can only be fixed by rolling back, so without a state to roll back to
+        // the input is turned away, instead of leaving the prediction in the simulation for good
+        let mispredicted = self.net_players[&player]
+            .request_inputs(frame, 1)
+            .filter(|window| window.status(frame) == Some(InputStatus::Predicted))
+            .map_or(false, |window| window.inputs[0] != input);
+        if mispredicted && !self.saved_rollback_states.contains(frame) {
+            return Err(NetcodeError::MissingSaveState(frame));
+        }
 
         let net_player = self.net_players.get_mut(&player).unwrap();
-        match net_player.add_input(frame, input) {
-            PredictionResult::Unpredicted => (),
-            PredictionResult::Correct => {
-                if self
-                    .net_players
-                    .iter()
-                    .all(|(_, net_player)| !net_player.is_predicted_input(frame))
-                {
-                    let removed_state = self.saved_rollback_states.remove(&frame);
-                    assert!(
-                        removed_state.is_some(),
-                        "Correct prediction with no remaining predictions for that frame should have a corresponding save state to drop."
-                    );
+        let prediction = net_player
+            .add_input(frame, input)
+            .map_err(|error| NetcodeError::from_history(error, player))?;
+        match prediction {
+            // save states get dropped once everyone's confirmed the frame, see update
+            PredictionResult::Unpredicted | PredictionResult::Correct => (),
+            PredictionResult::Wrong => {
+                self.stats.add_misprediction(player);
+
+                // rolling back to the oldest wrong frame resimulates every later one too
+                self.rollback_to = Some(
+                    self.rollback_to
+                        .map(|old_frame| old_frame.min(frame))
+                        .unwrap_or(frame),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // spectators start on the earliest frame that could still be rolled back, everything before
+    // it is confirmed, see spectator_start, the held inputs leading up to it get sent too
+    pub fn add_spectator(&mut self) -> SpectatorHandle {
+        let handle = SpectatorHandle(self.next_spectator);
+        self.next_spectator += 1;
+        // cleaning keeps these for resimulating the start frame, so they're still around
+        let first_frame =
+            (self.earliest_unconfirmed_frame() + 1).saturating_sub(self.held_input_count);
+        self.spectators.insert(handle, first_frame);
+        handle
+    }
+    // the next frame the spectator gets inputs for
+    pub fn spectator_next_frame(&self, spectator: SpectatorHandle) -> Result<usize, NetcodeError> {
+        self.spectators
+            .get(&spectator)
+            .cloned()
+            .ok_or(NetcodeError::UnknownSpectator(spectator))
+    }
+    pub fn remove_spectator(&mut self, spectator: SpectatorHandle) -> Result<(), NetcodeError> {
+        self.spectators
+            .remove(&spectator)
+            .map(|_| ())
+            .ok_or(NetcodeError::UnknownSpectator(spectator))
+    }
+
+    pub fn handle_spectator_packet(
+        &mut self,
+        spectator: SpectatorHandle,
+        packet: Packet<Input>,
+    ) -> Result<(), NetcodeError> {
+        let next_frame = self
+            .spectators
+            .get_mut(&spectator)
+            .ok_or(NetcodeError::UnknownSpectator(spectator))?;
+        if let Packet::SpectatorAck(frame) = packet {
+            let frame = frame
+                .resolve(*next_frame)
+                .ok_or(NetcodeError::FrameOutOfRange)?;
+            *next_frame = frame.max(*next_frame);
+        }
+        Ok(())
+    }
+
+    // inputs are confirmed once every player has given us their real input for that frame
+    fn is_input_confirmed(&self, frame: usize) -> bool {
+        self.players
+            .iter()
+            .all(|info| self.history(info.id).is_confirmed_input(frame))
+    }
+
+    // only valid after checking is_input_confirmed
+    fn confirmed_input(&self, info: &PlayerInfo, frame: usize) -> &Input {
+        &self
+            .history(info.id)
+            .request_inputs(frame, 1)
+            .unwrap()
+            .inputs[0]
+    }
+
+    fn history(&self, player: PlayerHandle) -> &dyn InputHistory<Input> {
+        match self.local_players.get(&player) {
+            Some(local_player) => local_player,
+            None => &self.net_players[&player],
+        }
+    }
+
+    // replays hold every player from the first frame on, so nobody can join once one has started
+    fn check_not_recording(&self) -> Result<(), NetcodeError> {
+        if self.recording.is_some() {
+            Err(NetcodeError::PlayerAddedWhileRecording)
+        } else {
+            Ok(())
+        }
+    }
+
+    // replays have to start from the first frame, so this has to be called before the first update,
+    // and after every player was added
+    pub fn start_recording(&mut self) -> Result<(), NetcodeError> {
+        if self.current_frame > 0 {
+            return Err(NetcodeError::RecordingStartedLate);
+        }
+        self.recording = Some(Replay::new(
+            ReplaySettings {
+                held_input_count: self.held_input_count,
+                input_delay: self.input_delay,
+                allowed_rollback: self.allowed_rollback,
+            },
+            self.players.iter().map(|info| info.id).collect(),
+        ));
+        Ok(())
+    }
+    pub fn stop_recording(&mut self) -> Option<Replay<Input>> {
+        self.recording.take()
+    }
+    pub fn recording(&self) -> Option<&Replay<Input>> {
+        self.recording.as_ref()
+    }
+
+    fn record_confirmed_inputs(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            let mut frame = recording.frame_count();
+            while !self.players.is_empty() && self.is_input_confirmed(frame) {
+                for (inputs, info) in recording.inputs.iter_mut().zip(self.players.iter()) {
+                    inputs.push(self.confirmed_input(info, frame).clone());
                 }
+                frame += 1;
             }
-            PredictionResult::Wrong => {
-                let state = self.saved_rollback_states.remove(&frame);
-
-                // prefer to rollback to an older frame if one's available
-                if let Some(state) = state {
-                    if let Some((old_frame, _)) = self.rollback_to {
-                        if old_frame > frame {
-                            // we're rollbacking to an older frame so we can replace it
-                            self.rollback_to = Some((frame, state));
-                        } else {
-                            // should be ok to lose this state, because the rollback will recreate it if necessary
-                            // or we're already rolling back to the correct place
+            self.recording = Some(recording);
+        }
+    }
+
+    // sends every spectator the confirmed inputs they haven't acked yet,
+    // spectators that fell too far behind get dropped, with a SpectatorDropped event
+    pub fn spectator_packets(&mut self) -> Vec<(SpectatorHandle, Packet<Input>)> {
+        // anything older than this might have been cleaned out of the input histories
+        let oldest_frame = self.oldest_spectator_frame();
+        let lagging: Vec<_> = self
+            .spectators
+            .iter()
+            .filter(|(_, next_frame)| **next_frame < oldest_frame)
+            .map(|(spectator, _)| *spectator)
+            .collect();
+        for spectator in lagging {
+            self.spectators.remove(&spectator);
+            self.events
+                .push_back(NetcodeEvent::SpectatorDropped(spectator));
+        }
+
+        self.spectators
+            .iter()
+            .filter_map(|(spectator, next_frame)| {
+                let frames = (*next_frame..*next_frame + self.packet_buffer_size)
+                    .take_while(|frame| self.is_input_confirmed(*frame))
+                    .count();
+                if frames == 0 {
+                    None
+                } else {
+                    let inputs = self
+                        .players
+                        .iter()
+                        .map(|info| {
+                            PackedInputs(
+                                (*next_frame..*next_frame + frames)
+                                    .map(|frame| self.confirmed_input(info, frame).clone())
+                                    .collect(),
+                            )
+                        })
+                        .collect();
+                    Some((
+                        *spectator,
+                        Packet::Spectate(WireFrame::new(*next_frame), inputs),
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    // returns false if the player already disconnected, and we shouldn't listen to them anymore
+    fn heard_from(&mut self, player: PlayerHandle) -> Result<bool, NetcodeError> {
+        self.check_net_player(player)?;
+
+        let connection = self.connections.get_mut(&player).unwrap();
+        if connection.status == ConnectionStatus::Disconnected {
+            return Ok(false);
+        }
+
+        if connection.status == ConnectionStatus::Interrupted {
+            self.events.push_back(NetcodeEvent::PeerResumed(player));
+        }
+        connection.last_received = (self.clock)();
+        connection.status = ConnectionStatus::Connected;
+        connection.heard_from = true;
+
+        if !self.synchronized
+            && self
+                .connections
+                .values()
+                .all(|connection| connection.heard_from)
+        {
+            self.synchronized = true;
+            self.events.push_back(NetcodeEvent::Synchronized);
+        }
+        Ok(true)
+    }
+
+    fn update_connections(&mut self) -> Result<(), NetcodeError> {
+        let now = (self.clock)();
+        for (player, connection) in self.connections.iter_mut() {
+            let silence = now.duration_since(connection.last_received);
+            if connection.status == ConnectionStatus::Disconnected {
+                continue;
+            } else if silence >= self.disconnect_timeout {
+                connection.status = ConnectionStatus::Disconnected;
+                self.events
+                    .push_back(NetcodeEvent::PeerDisconnected(*player));
+            } else if silence >= self.interrupt_timeout
+                && connection.status == ConnectionStatus::Connected
+            {
+                connection.status = ConnectionStatus::Interrupted;
+                self.events
+                    .push_back(NetcodeEvent::PeerInterrupted(*player));
+            }
+        }
+
+        let disconnected: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.status == ConnectionStatus::Disconnected)
+            .map(|(player, _)| *player)
+            .collect();
+        for player in disconnected {
+            match self.disconnect_policy {
+                DisconnectPolicy::EndSession => {
+                    return Err(NetcodeError::PlayerDisconnected(player))
+                }
+                DisconnectPolicy::ContinueWithDefaultInput => {
+                    // overwrite anything we predicted for them with the default too, which
+                    // will rollback if necessary
+                    let first_frame = self.current_frame.saturating_sub(self.allowed_rollback + 1);
+                    for frame in first_frame..=self.current_frame {
+                        let net_player = &self.net_players[&player];
+                        if !net_player.has_input(frame) || net_player.is_predicted_input(frame) {
+                            self.handle_net_input(frame, Input::default(), player)?;
                         }
-                    } else {
-                        // we're not already rolling back, so lets go
-                        self.rollback_to = Some((frame, state));
                     }
-                } else if self.rollback_to.is_none() {
-                    // panic if we don't have a state to rollback to
-                    // we should never actually be in a state where we've got predicted input for a frame
-                    // and no currently rollbacking frame AND no frame to rollback to waiting
-                    unreachable!(
-                        "No state to rollback to even though there is a mispredicted input."
-                    );
                 }
             }
         }
+        Ok(())
     }
 
     // must return to sender
-    pub fn handle_packet(&mut self, packet: Packet<Input>) -> Option<Packet<Input>> {
+    pub fn handle_packet(
+        &mut self,
+        packet: Packet<Input>,
+    ) -> Result<Option<Packet<Input>>, NetcodeError> {
         match packet {
-            Packet::Inputs(player_handle, sent_on_frame, start_frame, inputs) => {
-                assert!(
-                    self.players[player_handle.0].player_type == PlayerType::Net,
-                    "Must handle networked input for a networked player."
-                );
-
-                self.skip_frames = self
-                    .current_frame
-                    .checked_sub(sent_on_frame + self.get_network_delay(player_handle))
-                    .unwrap_or(0)
-                    .max(self.skip_frames);
-                for (idx, input) in inputs.into_iter().enumerate() {
+            Packet::Inputs(
+                player_handle,
+                sent_on_frame,
+                remote_advantage,
+                start_frame,
+                inputs,
+                acks,
+            ) => {
+                if !self.heard_from(player_handle)? {
+                    return Ok(None);
+                }
+                let sent_on_frame = self.resolve_frame(sent_on_frame)?;
+                let start_frame = self.resolve_frame(start_frame)?;
+                // by the time we got this, they should be network_delay frames past when they sent it
+                let remote_frame = sent_on_frame + self.get_network_delay(player_handle)?;
+                self.time_sync
+                    .add_local_advantage(self.current_frame as i32 - remote_frame as i32);
+                self.time_sync.add_remote_advantage(remote_advantage);
+                for (idx, input) in inputs.0.into_iter().enumerate() {
                     let frame = start_frame + idx;
-                    self.handle_net_input(frame, input, player_handle);
+                    self.handle_net_input(frame, input, player_handle)?;
+                }
+                // the acks are for every player the sender doesn't control, only ours matter here
+                for (ack_handle, next_frame) in acks {
+                    if self.local_players.contains_key(&ack_handle) {
+                        self.handle_input_ack(ack_handle, next_frame)?;
+                    }
+                }
+                if self.local_players.is_empty() {
+                    // we never send inputs, so nothing else would carry the ack back
+                    let next_frame = self.net_players[&player_handle].next_unconfirmed_frame();
+                    Ok(Some(Packet::InputAck(
+                        player_handle,
+                        WireFrame::new(next_frame),
+                    )))
+                } else {
+                    Ok(None)
                 }
-                None
+            }
+            Packet::InputAck(player_handle, next_frame) => {
+                self.check_local_player(player_handle)?;
+                self.handle_input_ack(player_handle, next_frame)?;
+                Ok(None)
             }
             Packet::Request(frame) => {
+                let frame = self.resolve_frame(frame)?;
                 let requested_data: Vec<_> = self
                     .local_players
                     .iter()
-                    .map(|(handle, player)| (handle, player.get_inputs(frame, 1)))
-                    .map(|(handle, (range, player))| {
-                        (*handle, range.first, player.iter().cloned().collect())
+                    // we can't provide inputs we've already cleaned up, or haven't made yet
+                    .filter_map(|(handle, player)| {
+                        let newest_frame = player.next_unconfirmed_frame().checked_sub(1)?;
+                        let amt = (newest_frame + 1).checked_sub(frame)?;
+                        // everything from the requested frame on, they'll need it all anyway
+                        player.request_inputs(newest_frame, amt).map(|window| {
+                            (
+                                *handle,
+                                WireFrame::new(window.range.first),
+                                PackedInputs(window.inputs.to_vec()),
+                            )
+                        })
                     })
                     .collect();
                 if requested_data.is_empty() {
                     // we don't have any local players or anything to send back.
-                    None
+                    Ok(None)
                 } else {
-                    Some(Packet::Provide(requested_data))
+                    Ok(Some(Packet::Provide(requested_data)))
                 }
             }
             Packet::Provide(inputs_list) => {
                 for (player_handle, frame, inputs) in inputs_list {
-                    for (idx, input) in inputs.into_iter().enumerate() {
-                        self.handle_net_input(frame + idx, input, player_handle);
+                    if !self.heard_from(player_handle)? {
+                        continue;
+                    }
+                    let frame = self.resolve_frame(frame)?;
+                    for (idx, input) in inputs.0.into_iter().enumerate() {
+                        self.handle_net_input(frame + idx, input, player_handle)?;
                     }
                 }
-                None
+                Ok(None)
             }
+            Packet::Checksum(player, frame, checksum) => {
+                if !self.heard_from(player)? {
+                    return Ok(None);
+                }
+                let frame = self.resolve_frame(frame)?;
+                self.remote_checksums.insert((player, frame), checksum);
+                self.check_checksums();
+                Ok(None)
+            }
+            // these only go between us and our spectators
+            Packet::Spectate(..) | Packet::SpectatorAck(_) => Ok(None),
         }
     }
 
-    pub fn update<'a, Game: RollbackableGameState<SavedState = GameState, Input = Input>>(
-        &'a mut self,
-        game: &mut Game,
-    ) -> Option<Packet<Input>> {
-        if let Some((rollback_frame, state)) = self.rollback_to.take() {
-            game.load_state(state);
-
-            for rollback_current_frame in rollback_frame..self.current_frame {
-                assert!(
-                    !self
-                        .net_players
-                        .iter()
-                        .any(|(_, net_player)| net_player.is_empty_input(rollback_current_frame)),
-                    "Can't rollback through empty data."
-                );
-
-                if self
-                    .net_players
-                    .iter()
-                    .any(|(_, net_player)| net_player.is_predicted_input(rollback_current_frame))
-                {
-                    self.saved_rollback_states
-                        .insert(rollback_current_frame, game.save_state());
-                }
-                for (_, net_player) in self
-                    .net_players
-                    .iter_mut()
-                    .filter(|(_, net_player)| net_player.is_predicted_input(rollback_current_frame))
-                {
-                    net_player.repredict(rollback_current_frame);
-                }
+    // the held inputs of the player ending on frame, along with the status of the input for frame
+    fn held_inputs(
+        &self,
+        player: PlayerHandle,
+        frame: usize,
+    ) -> Result<(&[Input], InputStatus), NetcodeError> {
+        let window = self
+            .history(player)
+            .request_inputs(frame, self.held_input_count)
+            // the last frame of input in the queue, should match the frame being simulated
+            .filter(|window| window.range.last == frame);
+        match window.and_then(|window| window.status(frame).map(|status| (window, status))) {
+            Some((window, status)) if status != InputStatus::Missing => Ok((window.inputs, status)),
+            _ => Err(NetcodeError::MissingInput { frame, player }),
+        }
+    }
+    fn check_inputs(&self, frame: usize) -> Result<(), NetcodeError> {
+        for info in self.players.iter() {
+            self.held_inputs(info.id, frame)?;
+        }
+        Ok(())
+    }
 
-                game.advance_frame(InputSet {
-                    inputs: self
-                        .players
-                        .iter()
-                        .map(|info| {
-                            //
-                            let (range, inputs) = match info.player_type {
-                                PlayerType::Local => self.local_players[&info.id]
-                                    .get_inputs(rollback_current_frame, self.held_input_count),
-                                PlayerType::Net => self.net_players[&info.id]
-                                    .get_inputs(rollback_current_frame, self.held_input_count),
-                            };
-                            assert!(range.last == rollback_current_frame, "The last frame of input in the queue, should match the currently rollbacking frame.");
-                            inputs
-                        })
-                        .collect(),
-                });
-            }
+    // the inputs for an AdvanceFrame request, borrowed straight out of the input histories
+    pub fn inputs(
+        &self,
+        frame: usize,
+        is_resimulating: bool,
+    ) -> Result<InputSet<'_, Input>, NetcodeError> {
+        let mut inputs = Vec::with_capacity(self.players.len());
+        let mut statuses = Vec::with_capacity(self.players.len());
+        for info in self.players.iter() {
+            let (held_inputs, status) = self.held_inputs(info.id, frame)?;
+            inputs.push(held_inputs);
+            statuses.push(status);
         }
+        Ok(InputSet {
+            frame,
+            is_resimulating,
+            inputs,
+            players: &self.handles,
+            statuses,
+        })
+    }
 
-        if self.current_frame % self.held_input_count == 0 {
-            let clear_target = self
-                .current_frame
-                .checked_sub(self.held_input_count + self.allowed_rollback)
-                .unwrap_or(0);
-            for (_, local_player) in self.local_players.iter_mut() {
-                local_player.clean(clear_target);
-            }
-            for (_, net_player) in self.net_players.iter_mut() {
-                net_player.clean(clear_target);
+    // runs the game through the requests from update_requests
+    pub fn update<Game: RollbackableGameState<SavedState = GameState, Input = Input>>(
+        &mut self,
+        game: &mut Game,
+    ) -> Result<Option<Packet<Input>>, NetcodeError> {
+        let UpdateRequests { requests, packet } = self.update_requests()?;
+        for request in requests {
+            match request {
+                NetcodeRequest::LoadState(frame) => game.load_state(self.take_state(frame)?),
+                NetcodeRequest::SaveState(frame) => {
+                    self.save_state(frame, game.save_state(), game.checksum())?
+                }
+                NetcodeRequest::AdvanceFrame {
+                    frame,
+                    is_resimulating,
+                } => game.advance_frame(self.inputs(frame, is_resimulating)?),
             }
         }
+        Ok(packet)
+    }
 
-        let earliest_predicted_input_diff = self
-            .saved_rollback_states
-            .keys()
-            .min()
-            .and_then(|frame| self.current_frame.checked_sub(*frame))
-            .unwrap_or(0);
+    // hands back the state saved for a LoadState request
+    pub fn take_state(&mut self, frame: usize) -> Result<GameState, NetcodeError> {
+        self.saved_rollback_states
+            .take(frame)
+            .ok_or(NetcodeError::MissingSaveState(frame))
+    }
 
-        if self.skip_frames > 0 {
-            self.skip_frames -= 1;
-            None
-        } else if self
-            .local_players
+    // stores the state for a SaveState request, the checksum is only needed to detect desyncs
+    pub fn save_state(
+        &mut self,
+        frame: usize,
+        state: GameState,
+        checksum: Option<u64>,
+    ) -> Result<(), NetcodeError> {
+        self.saved_rollback_states
+            .save(frame, state)
+            .map_err(|_| NetcodeError::SaveStateStoreFull(frame))?;
+
+        if self.sync_test.is_some() {
+            let checksum = checksum.ok_or(NetcodeError::MissingChecksum)?;
+            // the first time a frame gets saved is the original, every resave after that is a resimulation
+            match self.sync_test_checksums.get(&frame) {
+                Some(original_checksum) => {
+                    if *original_checksum != checksum && self.sync_test_failure.is_none() {
+                        let failure = SyncTestFailure {
+                            frame,
+                            original_checksum: *original_checksum,
+                            resimulated_checksum: checksum,
+                        };
+                        self.sync_test_failure = Some(failure);
+                        self.events.push_back(NetcodeEvent::SyncTestFailed(failure));
+                    }
+                }
+                None => {
+                    self.sync_test_checksums.insert(frame, checksum);
+                }
+            }
+        } else if frame % self.checksum_interval == 0 {
+            if let Some(checksum) = checksum {
+                // resimulating a frame overwrites the checksum we got while predicting it
+                self.local_checksums.insert(frame, checksum);
+            }
+        }
+        Ok(())
+    }
+
+    // everything the game needs to do this update, in the order it needs to be done,
+    // along with a packet to send to the other players, the requests read from the input
+    // histories as they run, so they have to be run before handing the client anything else
+    pub fn update_requests(&mut self) -> Result<UpdateRequests<Input>, NetcodeError> {
+        self.update_connections()?;
+
+        let from_frame = self.current_frame;
+        let pending_rollback = self.rollback_to.take();
+        let mut requests = Vec::new();
+        let result = match self.build_requests(pending_rollback, &mut requests) {
+            Ok(packet) => {
+                if let Some(rollback_frame) = pending_rollback {
+                    self.stats.add_rollback(from_frame - rollback_frame);
+                    self.events.push_back(NetcodeEvent::RollbackPerformed {
+                        from: from_frame,
+                        to: rollback_frame,
+                    });
+                }
+                if self.current_frame != from_frame {
+                    self.waiting_for.clear();
+                }
+                Ok(UpdateRequests { requests, packet })
+            }
+            Err(error) => {
+                // none of the requests get run, so the rollback still has to happen
+                self.rollback_to = self.rollback_to.into_iter().chain(pending_rollback).min();
+                Err(error)
+            }
+        };
+        while self.events.len() > MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        result
+    }
+
+    fn rollback_requests(
+        &mut self,
+        rollback_frame: usize,
+        requests: &mut Vec<NetcodeRequest>,
+    ) -> Result<(), NetcodeError> {
+        if !self.saved_rollback_states.contains(rollback_frame) {
+            return Err(NetcodeError::MissingSaveState(rollback_frame));
+        }
+        // can't rollback through empty data, checked up front so nothing changes if we can't
+        for frame in rollback_frame..self.current_frame {
+            self.check_inputs(frame)?;
+        }
+
+        requests.push(NetcodeRequest::LoadState(rollback_frame));
+        for frame in rollback_frame..self.current_frame {
+            // resimulating invalidates every state after the rollback, so they all get resaved
+            self.save_request(frame, requests);
+            for (handle, net_player) in self
+                .net_players
+                .iter_mut()
+                .filter(|(_, net_player)| net_player.is_predicted_input(frame))
+            {
+                net_player
+                    .repredict(frame, self.prediction_strategies[handle].as_ref())
+                    .map_err(|error| NetcodeError::from_history(error, *handle))?;
+            }
+            requests.push(NetcodeRequest::AdvanceFrame {
+                frame,
+                is_resimulating: true,
+            });
+        }
+        Ok(())
+    }
+
+    fn build_requests(
+        &mut self,
+        pending_rollback: Option<usize>,
+        requests: &mut Vec<NetcodeRequest>,
+    ) -> Result<Option<Packet<Input>>, NetcodeError> {
+        if let Some(rollback_frame) = pending_rollback {
+            self.rollback_requests(rollback_frame, requests)?;
+        }
+
+        // has to happen before clean, so we don't miss any inputs
+        self.record_confirmed_inputs();
+
+        // nothing can rollback to a confirmed frame, so we don't need its save state,
+        // but the rollback we just requested still has to load its state
+        let earliest_unconfirmed_frame = pending_rollback
+            .into_iter()
+            .fold(self.earliest_unconfirmed_frame(), usize::min);
+        self.saved_rollback_states
+            .drop_before(earliest_unconfirmed_frame);
+        self.sync_test_checksums
+            .retain(|frame, _| *frame >= earliest_unconfirmed_frame);
+
+        // cleaning is cheap, so we do it every frame to keep the histories from filling up
+        // the requests borrow their inputs when they run, so the rollback still needs its inputs too
+        let clear_target = self.clean_target(earliest_unconfirmed_frame);
+        for (handle, local_player) in self.local_players.iter_mut() {
+            local_player
+                .clean(clear_target)
+                .map_err(|error| NetcodeError::from_history(error, *handle))?;
+        }
+        for (handle, net_player) in self.net_players.iter_mut() {
+            net_player
+                .clean(clear_target)
+                .map_err(|error| NetcodeError::from_history(error, *handle))?;
+        }
+        // the other side might be a few frames behind confirming these, so they're kept longer
+        let oldest_checksum = self
+            .current_frame
+            .saturating_sub(self.held_input_count + self.allowed_rollback);
+        self.local_checksums
+            .retain(|frame, _| *frame >= oldest_checksum);
+        self.remote_checksums
+            .retain(|(_, frame), _| *frame >= oldest_checksum);
+        self.check_checksums();
+
+        if let Some(check_distance) = self.sync_test {
+            self.sync_test_requests(check_distance, requests)?;
+            return Ok(None);
+        }
+
+        let earliest_predicted_input_diff = self.current_frame - self.earliest_unconfirmed_frame();
+
+        if self.current_frame >= self.next_time_sync_frame {
+            self.next_time_sync_frame = self.current_frame + self.time_sync_interval;
+            let frames_ahead = self.time_sync.recommended_frame_delay();
+            self.events
+                .push_back(NetcodeEvent::TimeSync { frames_ahead });
+            if self.automatic_time_sync {
+                self.slowdown_rate = frames_ahead.max(0.0) / self.time_sync_interval as f32;
+            }
+        }
+        self.slowdown += self.slowdown_rate;
+
+        if self.slowdown >= 1.0 {
+            // skip single frames spread out over the interval, instead of stalling all at once
+            self.slowdown -= 1.0;
+            self.stats.skipped_frames += 1;
+            Ok(None)
+        } else if self
+            .local_players
             .iter()
             .all(|(_, local_player)| local_player.has_input(self.current_frame))
             && self
@@ -359,73 +1312,139 @@ impl<Input: Clone + Default + PartialEq + std::fmt::Debug, GameState: std::fmt::
                 .all(|(_, net_players)| net_players.has_input(self.current_frame))
             && earliest_predicted_input_diff < self.allowed_rollback
         {
-            game.advance_frame(InputSet {
-                inputs: self
-                    .players
-                    .iter()
-                    .map(|info| {
-                        //
-                        let (range, inputs) = match info.player_type {
-                            PlayerType::Local => self.local_players[&info.id]
-                                .get_inputs(self.current_frame, self.held_input_count),
-                            PlayerType::Net => self.net_players[&info.id]
-                                .get_inputs(self.current_frame, self.held_input_count),
-                        };
-                        assert!(
-                            range.last == self.current_frame,
-                            "The last frame of input in the queue, should match the current frame."
-                        );
-                        inputs
-                    })
-                    .collect(),
+            self.save_request(self.current_frame, requests);
+            self.check_inputs(self.current_frame)?;
+            requests.push(NetcodeRequest::AdvanceFrame {
+                frame: self.current_frame,
+                is_resimulating: false,
             });
 
             self.current_frame += 1;
 
-            None
+            Ok(self.checksum_packet())
         } else {
             if earliest_predicted_input_diff < self.allowed_rollback
                 && self.current_frame > self.allowed_rollback
             {
-                self.saved_rollback_states
-                    .insert(self.current_frame, game.save_state());
-
                 let current_frame = self.current_frame;
 
-                for net_player in self
+                for (handle, net_player) in self
                     .net_players
-                    .values_mut()
-                    .filter(|net_player| net_player.is_empty_input(current_frame))
+                    .iter_mut()
+                    .filter(|(_, net_player)| net_player.is_empty_input(current_frame))
                 {
-                    net_player.predict(self.current_frame);
+                    net_player
+                        .predict(current_frame, self.prediction_strategies[handle].as_ref())
+                        .map_err(|error| NetcodeError::from_history(error, *handle))?;
+                    self.stats.add_prediction(*handle);
                 }
 
-                game.advance_frame(InputSet {
-                    inputs: self
-                        .players
-                        .iter()
-                        .map(|info| {
-                            let (range, inputs) = match info.player_type {
-                                PlayerType::Local => self.local_players[&info.id]
-                                    .get_inputs(self.current_frame, self.held_input_count),
-                                PlayerType::Net => self.net_players[&info.id]
-                                    .get_inputs(self.current_frame, self.held_input_count),
-                            };
-                            assert!(range.last == self.current_frame, "The last frame of input in the queue, should match the currently being predicted frame.");
-                            inputs
-                        })
-                        .collect(),
+                self.save_request(self.current_frame, requests);
+                self.check_inputs(self.current_frame)?;
+                requests.push(NetcodeRequest::AdvanceFrame {
+                    frame: self.current_frame,
+                    is_resimulating: false,
                 });
                 self.current_frame += 1;
 
-                None
+                Ok(self.checksum_packet())
             } else {
-                Some(Packet::Request(
-                    self.current_frame - earliest_predicted_input_diff,
-                ))
+                let requested_frame = self.current_frame - earliest_predicted_input_diff;
+                self.stats.stalled_frames += 1;
+                let waiting_for: Vec<_> = self
+                    .net_players
+                    .iter()
+                    .filter(|(_, net_player)| {
+                        !net_player.has_input(requested_frame)
+                            || net_player.is_predicted_input(requested_frame)
+                    })
+                    .map(|(player, _)| *player)
+                    .collect();
+                for player in waiting_for.iter() {
+                    if !self.waiting_for.contains(player) {
+                        self.events
+                            .push_back(NetcodeEvent::WaitingForPlayer(*player));
+                    }
+                }
+                self.waiting_for = waiting_for;
+                Ok(Some(Packet::Request(WireFrame::new(requested_frame))))
             }
         }
     }
+
+    // only frames with an input that might still change can be rolled back to, the rest are only
+    // saved for their checksums
+    fn save_request(&self, frame: usize, requests: &mut Vec<NetcodeRequest>) {
+        if !self.is_input_confirmed(frame) || frame % self.checksum_interval == 0 {
+            requests.push(NetcodeRequest::SaveState(frame));
+        }
+    }
+
+    // checksums get compared as the states are saved, see save_state
+    fn sync_test_requests(
+        &mut self,
+        check_distance: usize,
+        requests: &mut Vec<NetcodeRequest>,
+    ) -> Result<(), NetcodeError> {
+        let current_frame = self.current_frame;
+        if !self
+            .local_players
+            .values()
+            .all(|local_player| local_player.has_input(current_frame))
+        {
+            return Ok(());
+        }
+
+        requests.push(NetcodeRequest::SaveState(current_frame));
+        self.check_inputs(current_frame)?;
+        requests.push(NetcodeRequest::AdvanceFrame {
+            frame: current_frame,
+            is_resimulating: false,
+        });
+        self.current_frame += 1;
+        // the original result of this frame, the next update's save compares the resimulated one against it
+        requests.push(NetcodeRequest::SaveState(self.current_frame));
+
+        if let Some(rollback_frame) = self.current_frame.checked_sub(check_distance) {
+            requests.push(NetcodeRequest::LoadState(rollback_frame));
+
+            for frame in rollback_frame..self.current_frame {
+                requests.push(NetcodeRequest::SaveState(frame));
+                self.check_inputs(frame)?;
+                requests.push(NetcodeRequest::AdvanceFrame {
+                    frame,
+                    is_resimulating: true,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<
+        Input: Clone + Default + PartialEq + std::fmt::Debug,
+        GameState: Clone + std::fmt::Debug,
+        Store: SaveStateStore<GameState>,
+    > NetcodeClient<Input, GameState, Store>
+{
+    // where a spectator added right now starts, the frame to hand to SpectatorClient::joining,
+    // and the state from right before it, none if that's the game's current state,
+    // anything later could still be rolled back, so it can't be handed out
+    pub fn spectator_start(&mut self) -> Result<(usize, Option<GameState>), NetcodeError> {
+        let frame = self.earliest_unconfirmed_frame();
+        if frame == self.current_frame {
+            return Ok((frame, None));
+        }
+        // the store only hands out states by taking them, so it gets put right back
+        let state = self
+            .saved_rollback_states
+            .take(frame)
+            .ok_or(NetcodeError::MissingSaveState(frame))?;
+        self.saved_rollback_states
+            .save(frame, state.clone())
+            .map_err(|_| NetcodeError::SaveStateStoreFull(frame))?;
+        Ok((frame, Some(state)))
+    }
 }
 
 pub trait RollbackableGameState {
@@ -434,4 +1453,845 @@ pub trait RollbackableGameState {
     fn advance_frame(&mut self, input: InputSet<'_, Self::Input>);
     fn save_state(&self) -> Self::SavedState;
     fn load_state(&mut self, load: Self::SavedState);
+    // used to detect desyncs, only needs to be implemented if you want them detected
+    fn checksum(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net_client::MemoryHub;
+    use spectator_client::SpectatorClient;
+
+    struct Counter(i64);
+
+    impl RollbackableGameState for Counter {
+        type Input = i32;
+        type SavedState = i64;
+        fn advance_frame(&mut self, input: InputSet<'_, Self::Input>) {
+            for inputs in input.inputs.iter() {
+                self.0 += *inputs.last().unwrap() as i64;
+            }
+        }
+        fn save_state(&self) -> Self::SavedState {
+            self.0
+        }
+        fn load_state(&mut self, load: Self::SavedState) {
+            self.0 = load;
+        }
+    }
+
+    // a client for a two player match, along with the handle of its local player
+    fn client(local: usize) -> (NetcodeClient<i32, i64>, PlayerHandle) {
+        match_client(2, local)
+    }
+    fn match_client(players: usize, local: usize) -> (NetcodeClient<i32, i64>, PlayerHandle) {
+        let mut client = NetcodeClient::new(4);
+        client.set_input_delay(0);
+        let mut local_handle = None;
+        for player in 0..players {
+            if player == local {
+                local_handle = Some(client.add_local_player(player).unwrap());
+            } else {
+                client.add_net_player(player).unwrap();
+            }
+        }
+        (client, local_handle.unwrap())
+    }
+
+    fn sent_inputs(packet: &Packet<i32>) -> usize {
+        match packet {
+            Packet::Inputs(_, _, _, _, inputs, _) => inputs.0.len(),
+            packet => panic!("expected inputs, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn inputs_carry_the_acks() {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        let (mut first_game, mut second_game) = (Counter(0), Counter(0));
+
+        for frame in 0..20 {
+            let to_second = first
+                .handle_local_input(frame, first_handle)
+                .unwrap()
+                .unwrap();
+            let to_first = second
+                .handle_local_input(frame, second_handle)
+                .unwrap()
+                .unwrap();
+            let sent = sent_inputs(&to_second);
+            match frame {
+                0 => assert_eq!(sent, 1),
+                // the ack for the last frame is always on its way back
+                1..=12 | 18..=19 => assert_eq!(sent, 2),
+                // a few packets from first get lost, so it keeps resending those inputs
+                // until second's inputs bring back the ack
+                15 => assert_eq!(sent, 4),
+                _ => (),
+            }
+            if !(12..=14).contains(&frame) {
+                assert!(second.handle_packet(to_second).unwrap().is_none());
+            }
+            assert!(first.handle_packet(to_first).unwrap().is_none());
+
+            first.update(&mut first_game).unwrap();
+            second.update(&mut second_game).unwrap();
+        }
+    }
+
+    fn saved_frames(requests: &[NetcodeRequest]) -> Vec<usize> {
+        requests
+            .iter()
+            .filter_map(|request| match request {
+                NetcodeRequest::SaveState(frame) => Some(*frame),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_frames_that_can_rollback_get_saved() {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        first.checksum_interval = 1000;
+        let (mut game, mut second_game) = (Counter(0), Counter(0));
+        let mut saved = Vec::new();
+
+        for frame in 0..30 {
+            let to_first = second
+                .handle_local_input(frame, second_handle)
+                .unwrap()
+                .unwrap();
+            let to_second = first
+                .handle_local_input(frame, first_handle)
+                .unwrap()
+                .unwrap();
+            second.handle_packet(to_second).unwrap();
+            second.update(&mut second_game).unwrap();
+            // second goes quiet for a few frames, so first has to predict them
+            if !(12..15).contains(&frame) {
+                first.handle_packet(to_first).unwrap();
+            }
+            let UpdateRequests { requests, .. } = first.update_requests().unwrap();
+            saved.extend(saved_frames(&requests));
+            for request in requests {
+                match request {
+                    NetcodeRequest::LoadState(frame) => {
+                        game.load_state(first.take_state(frame).unwrap())
+                    }
+                    NetcodeRequest::SaveState(frame) => first
+                        .save_state(frame, game.save_state(), game.checksum())
+                        .unwrap(),
+                    NetcodeRequest::AdvanceFrame {
+                        frame,
+                        is_resimulating,
+                    } => game.advance_frame(first.inputs(frame, is_resimulating).unwrap()),
+                }
+            }
+        }
+
+        // the checksum on frame 0, and the predicted frames
+        assert_eq!(saved, vec![0, 12, 13, 14]);
+        assert_eq!(first.stats().rollbacks, 1);
+    }
+
+    #[test]
+    fn players_cant_join_a_recording() {
+        let (mut client, _) = client(0);
+        client.start_recording().unwrap();
+
+        assert_eq!(
+            client.add_local_player(2),
+            Err(NetcodeError::PlayerAddedWhileRecording)
+        );
+        assert_eq!(
+            client.add_net_player(3),
+            Err(NetcodeError::PlayerAddedWhileRecording)
+        );
+        assert_eq!(client.stop_recording().unwrap().players.len(), 2);
+        assert!(client.add_net_player(3).is_ok());
+    }
+
+    #[test]
+    fn requests_get_every_input_from_the_requested_frame_on() {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        let (mut first_game, mut second_game) = (Counter(0), Counter(0));
+        for frame in 0..5 {
+            let to_second = first.handle_local_input(frame * 10, first_handle).unwrap();
+            let to_first = second.handle_local_input(frame, second_handle).unwrap();
+            second.handle_packet(to_second.unwrap()).unwrap();
+            first.handle_packet(to_first.unwrap()).unwrap();
+            first.update(&mut first_game).unwrap();
+            second.update(&mut second_game).unwrap();
+        }
+
+        match first.handle_packet(Packet::Request(WireFrame::new(2))) {
+            Ok(Some(Packet::Provide(provided))) => {
+                assert_eq!(provided.len(), 1);
+                let (player, start_frame, inputs) = &provided[0];
+                assert_eq!(*player, first_handle);
+                assert_eq!(start_frame.resolve(4), Some(2));
+                assert_eq!(inputs.0, vec![20, 30, 40]);
+            }
+            result => panic!("expected inputs to be provided, got {:?}", result),
+        }
+        // nothing to give for frames that haven't happened yet
+        assert!(first
+            .handle_packet(Packet::Request(WireFrame::new(6)))
+            .unwrap()
+            .is_none());
+    }
+
+    // like Counter, but when it drifts, every run of a frame adds a bit more than the last
+    struct Drifting {
+        counter: Counter,
+        runs: i64,
+        drifts: bool,
+    }
+
+    impl Drifting {
+        fn new(drifts: bool) -> Self {
+            Self {
+                counter: Counter(0),
+                runs: 0,
+                drifts,
+            }
+        }
+    }
+
+    impl RollbackableGameState for Drifting {
+        type Input = i32;
+        type SavedState = i64;
+        fn advance_frame(&mut self, input: InputSet<'_, Self::Input>) {
+            self.counter.advance_frame(input);
+            self.runs += 1;
+            if self.drifts {
+                self.counter.0 += self.runs;
+            }
+        }
+        fn save_state(&self) -> Self::SavedState {
+            self.counter.save_state()
+        }
+        fn load_state(&mut self, load: Self::SavedState) {
+            self.counter.load_state(load);
+        }
+        fn checksum(&self) -> Option<u64> {
+            Some(self.counter.0 as u64)
+        }
+    }
+
+    fn sync_test(game: &mut Drifting) -> NetcodeClient<i32, i64> {
+        let mut client = NetcodeClient::new(4);
+        let handle = client.add_local_player(0).unwrap();
+        client.set_sync_test(Some(2)).unwrap();
+        for _ in 0..10 {
+            client.handle_local_input(1, handle).unwrap();
+            client.update(game).unwrap();
+        }
+        client
+    }
+
+    #[test]
+    fn deterministic_games_pass_the_sync_test() {
+        let mut game = Drifting::new(false);
+        let mut client = sync_test(&mut game);
+
+        assert_eq!(client.sync_test_failure(), None);
+        assert!(client
+            .poll_events()
+            .all(|event| !matches!(event, NetcodeEvent::SyncTestFailed(_))));
+        assert_eq!(game.counter.0, 10);
+    }
+
+    #[test]
+    fn sync_test_catches_checksum_mismatches() {
+        let mut game = Drifting::new(true);
+        let mut client = sync_test(&mut game);
+
+        // frame 0 first ran as the first run, and got resimulated on the third
+        let failure = SyncTestFailure {
+            frame: 1,
+            original_checksum: 2,
+            resimulated_checksum: 4,
+        };
+        assert_eq!(client.sync_test_failure(), Some(failure));
+        let failures: Vec<_> = client
+            .poll_events()
+            .filter(|event| matches!(event, NetcodeEvent::SyncTestFailed(_)))
+            .collect();
+        assert_eq!(failures, vec![NetcodeEvent::SyncTestFailed(failure)]);
+    }
+
+    #[test]
+    fn sync_tests_are_local_only() {
+        let (mut client, _) = client(0);
+        assert_eq!(
+            client.set_sync_test(Some(2)),
+            Err(NetcodeError::SyncTestWithNetPlayers)
+        );
+
+        let mut client = NetcodeClient::<i32, i64>::new(4);
+        assert_eq!(
+            client.set_sync_test(Some(0)),
+            Err(NetcodeError::InvalidSyncTestDistance)
+        );
+        client.set_sync_test(Some(1)).unwrap();
+        assert_eq!(
+            client.add_net_player(1),
+            Err(NetcodeError::SyncTestWithNetPlayers)
+        );
+    }
+
+    // every client's packets go to every other client, later players' packets arrive first
+    fn play_match(games: &mut [Drifting]) -> Vec<NetcodeClient<i32, i64>> {
+        let players = games.len();
+        let (mut clients, handles): (Vec<_>, Vec<_>) = (0..players)
+            .map(|local| match_client(players, local))
+            .unzip();
+        let broadcast = |clients: &mut [NetcodeClient<i32, i64>],
+                         packets: Vec<Option<Packet<i32>>>| {
+            for (sender, packet) in packets.into_iter().enumerate().rev() {
+                if let Some(packet) = packet {
+                    for (_, client) in clients
+                        .iter_mut()
+                        .enumerate()
+                        .filter(|(receiver, _)| *receiver != sender)
+                    {
+                        client.handle_packet(packet.clone()).unwrap();
+                    }
+                }
+            }
+        };
+        for frame in 0..40 {
+            let inputs = clients
+                .iter_mut()
+                .zip(handles.iter())
+                .map(|(client, handle)| client.handle_local_input(frame, *handle).unwrap())
+                .collect();
+            broadcast(&mut clients, inputs);
+            let checksums = clients
+                .iter_mut()
+                .zip(games.iter_mut())
+                .map(|(client, game)| client.update(game).unwrap())
+                .collect();
+            broadcast(&mut clients, checksums);
+        }
+        clients
+    }
+
+    #[test]
+    fn matching_checksums_arent_desyncs() {
+        let mut clients = play_match(&mut [Drifting::new(false), Drifting::new(false)]);
+
+        assert_eq!(clients[0].desync(), None);
+        assert!(clients[0]
+            .poll_events()
+            .all(|event| !matches!(event, NetcodeEvent::Desync(_))));
+    }
+
+    #[test]
+    fn diverging_games_desync() {
+        let mut clients = play_match(&mut [Drifting::new(false), Drifting::new(true)]);
+
+        let desync = clients[0].desync().unwrap();
+        // the first checksummed frame already diverged
+        assert_eq!(desync.player, PlayerHandle(1));
+        assert_eq!(desync.frame, 10);
+        assert_eq!(desync.local_checksum, 45 * 2);
+        assert_ne!(desync.local_checksum, desync.remote_checksum);
+        let desyncs: Vec<_> = clients[0]
+            .poll_events()
+            .filter(|event| matches!(event, NetcodeEvent::Desync(_)))
+            .collect();
+        assert_eq!(desyncs, vec![NetcodeEvent::Desync(desync)]);
+    }
+
+    #[test]
+    fn desyncs_are_found_in_three_player_matches() {
+        let clients = play_match(&mut [
+            Drifting::new(false),
+            Drifting::new(false),
+            Drifting::new(true),
+        ]);
+
+        let desync = clients[0].desync().unwrap();
+        assert_eq!(desync.player, PlayerHandle(2));
+        assert_eq!(desync.frame, 10);
+        assert_eq!(desync.local_checksum, 45 * 3);
+        assert_eq!(
+            clients[1].desync().map(|desync| desync.player),
+            Some(PlayerHandle(2))
+        );
+    }
+
+    // a single input from a net player, without anything else going on
+    fn net_input(player: PlayerHandle, frame: usize, input: i32) -> Packet<i32> {
+        Packet::Inputs(
+            player,
+            WireFrame::new(frame),
+            0,
+            WireFrame::new(frame),
+            PackedInputs(vec![input]),
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn matching_peers_cant_cover_for_a_desynced_one() {
+        let (mut client, handle) = match_client(3, 0);
+        let mut game = Drifting::new(false);
+        // both checksums for frame 0 arrive before we confirmed it, the bad one first
+        for (player, checksum) in [(2, 1234), (1, 0)].iter() {
+            client
+                .handle_packet(Packet::Checksum(
+                    PlayerHandle(*player),
+                    WireFrame::new(0),
+                    *checksum,
+                ))
+                .unwrap();
+        }
+        assert_eq!(client.desync(), None);
+
+        for frame in 0..3 {
+            client.handle_local_input(0, handle).unwrap();
+            for player in 1..3 {
+                client
+                    .handle_packet(net_input(PlayerHandle(player), frame, 0))
+                    .unwrap();
+            }
+            client.update(&mut game).unwrap();
+        }
+
+        assert_eq!(
+            client.desync(),
+            Some(Desync {
+                player: PlayerHandle(2),
+                frame: 0,
+                local_checksum: 0,
+                remote_checksum: 1234,
+            })
+        );
+    }
+
+    // how much the first client's game moves over 3 frames of predicting second's input,
+    // after second held 5 for a while
+    fn predicted_total<S: PredictionStrategy<i32> + 'static>(strategy: S) -> i64 {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        first
+            .set_prediction_strategy(second_handle, strategy)
+            .unwrap();
+        let (mut first_game, mut second_game) = (Counter(0), Counter(0));
+        for frame in 0..15 {
+            let to_second = first.handle_local_input(0, first_handle).unwrap();
+            let to_first = second.handle_local_input(5, second_handle).unwrap();
+            second.handle_packet(to_second.unwrap()).unwrap();
+            second.update(&mut second_game).unwrap();
+            // second goes quiet for the last 3 frames
+            if frame < 12 {
+                first.handle_packet(to_first.unwrap()).unwrap();
+            }
+            first.update(&mut first_game).unwrap();
+        }
+        assert_eq!(first.stats().predictions[&second_handle].predictions, 3);
+        first_game.0 - 12 * 5
+    }
+
+    #[test]
+    fn prediction_strategies_are_per_player() {
+        assert_eq!(predicted_total(RepeatLast), 15);
+        assert_eq!(predicted_total(Neutral), 0);
+        assert_eq!(predicted_total(DecayToNeutral { frames: 1 }), 5);
+        let (mut client, local_handle) = client(0);
+        assert_eq!(
+            client.set_prediction_strategy(local_handle, Neutral),
+            Err(NetcodeError::NotNetPlayer(local_handle))
+        );
+    }
+
+    #[test]
+    fn mispredictions_without_a_save_state_are_turned_away() {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        let (mut game, mut second_game) = (Counter(0), Counter(0));
+        let mut late_input = None;
+
+        for frame in 0..13 {
+            let to_first = second
+                .handle_local_input(if frame < 12 { 5 } else { 7 }, second_handle)
+                .unwrap();
+            let to_second = first.handle_local_input(0, first_handle).unwrap();
+            second.handle_packet(to_second.unwrap()).unwrap();
+            second.update(&mut second_game).unwrap();
+            // second's input on frame 12 shows up after first already predicted it
+            if frame < 12 {
+                first.handle_packet(to_first.unwrap()).unwrap();
+            } else {
+                late_input = to_first;
+            }
+            let UpdateRequests { requests, .. } = first.update_requests().unwrap();
+            for request in requests {
+                match request {
+                    // the state of the predicted frame gets lost
+                    NetcodeRequest::SaveState(12) => (),
+                    NetcodeRequest::SaveState(frame) => first
+                        .save_state(frame, game.save_state(), game.checksum())
+                        .unwrap(),
+                    NetcodeRequest::LoadState(frame) => {
+                        game.load_state(first.take_state(frame).unwrap())
+                    }
+                    NetcodeRequest::AdvanceFrame {
+                        frame,
+                        is_resimulating,
+                    } => game.advance_frame(first.inputs(frame, is_resimulating).unwrap()),
+                }
+            }
+        }
+
+        assert_eq!(
+            first.handle_packet(late_input.unwrap()).unwrap_err(),
+            NetcodeError::MissingSaveState(12)
+        );
+        // the prediction stays, so the simulation still matches the inputs we have
+        assert_eq!(first.last_confirmed_frame(second_handle), Ok(Some(11)));
+        assert_eq!(first.inputs(12, false).unwrap().inputs[1], &[5, 5, 5, 5]);
+    }
+
+    fn connection_events(client: &mut NetcodeClient<i32, i64>) -> Vec<NetcodeEvent> {
+        client
+            .poll_events()
+            .filter(|event| {
+                matches!(
+                    event,
+                    NetcodeEvent::PeerInterrupted(_)
+                        | NetcodeEvent::PeerResumed(_)
+                        | NetcodeEvent::PeerDisconnected(_)
+                )
+            })
+            .collect()
+    }
+
+    // a client for the first player, whose timeouts follow the hub's clock
+    fn timed_client(
+        hub: &MemoryHub,
+        policy: DisconnectPolicy,
+    ) -> (NetcodeClient<i32, i64>, PlayerHandle, PlayerHandle) {
+        let (mut client, local_handle) = client(0);
+        client.set_disconnect_policy(policy);
+        client.set_clock(hub.clock());
+        (client, local_handle, PlayerHandle(1))
+    }
+
+    #[test]
+    fn quiet_peers_get_interrupted_and_resume() {
+        let hub = MemoryHub::new();
+        let (mut client, local_handle, net_handle) =
+            timed_client(&hub, DisconnectPolicy::EndSession);
+        let mut game = Counter(0);
+        client.handle_local_input(0, local_handle).unwrap();
+        client.handle_packet(net_input(net_handle, 0, 0)).unwrap();
+        client.update(&mut game).unwrap();
+
+        hub.advance(Duration::from_millis(700));
+        client.update(&mut game).unwrap();
+        assert_eq!(
+            client.connection_status(net_handle),
+            Ok(ConnectionStatus::Connected)
+        );
+
+        hub.advance(Duration::from_millis(100));
+        client.update(&mut game).unwrap();
+        assert_eq!(
+            client.connection_status(net_handle),
+            Ok(ConnectionStatus::Interrupted)
+        );
+        assert_eq!(
+            connection_events(&mut client),
+            vec![NetcodeEvent::PeerInterrupted(net_handle)]
+        );
+
+        client.handle_packet(net_input(net_handle, 1, 0)).unwrap();
+        assert_eq!(
+            client.connection_status(net_handle),
+            Ok(ConnectionStatus::Connected)
+        );
+        assert_eq!(
+            connection_events(&mut client),
+            vec![NetcodeEvent::PeerResumed(net_handle)]
+        );
+    }
+
+    #[test]
+    fn disconnects_end_the_session_by_default() {
+        let hub = MemoryHub::new();
+        let (mut client, _, net_handle) = timed_client(&hub, DisconnectPolicy::EndSession);
+
+        hub.advance(Duration::from_secs(5));
+
+        assert_eq!(
+            client.update(&mut Counter(0)).unwrap_err(),
+            NetcodeError::PlayerDisconnected(net_handle)
+        );
+        assert_eq!(
+            connection_events(&mut client),
+            vec![NetcodeEvent::PeerDisconnected(net_handle)]
+        );
+        // nothing they send afterwards brings them back
+        assert!(client
+            .handle_packet(net_input(net_handle, 0, 0))
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            client.connection_status(net_handle),
+            Ok(ConnectionStatus::Disconnected)
+        );
+    }
+
+    #[test]
+    fn disconnected_players_can_be_left_on_default_input() {
+        let hub = MemoryHub::new();
+        let (mut client, local_handle, net_handle) =
+            timed_client(&hub, DisconnectPolicy::ContinueWithDefaultInput);
+        let mut game = Counter(0);
+        for frame in 0..3 {
+            client.handle_local_input(1, local_handle).unwrap();
+            client
+                .handle_packet(net_input(net_handle, frame, 5))
+                .unwrap();
+            client.update(&mut game).unwrap();
+        }
+
+        hub.advance(Duration::from_secs(5));
+        for _ in 3..8 {
+            client.handle_local_input(1, local_handle).unwrap();
+            client.update(&mut game).unwrap();
+        }
+
+        assert_eq!(
+            connection_events(&mut client),
+            vec![NetcodeEvent::PeerDisconnected(net_handle)]
+        );
+        // they held 5 while they were around, and nothing after
+        assert_eq!(game.0, 3 * 6 + 5);
+        assert_eq!(client.confirmed_frame(), Some(7));
+    }
+
+    #[test]
+    fn spectators_join_from_the_last_confirmed_state() {
+        let (mut host, local_handle) = client(0);
+        let net_handle = PlayerHandle(1);
+        let mut game = Counter(0);
+        // the net player's inputs from frame 15 on are late, so those frames are predicted
+        for frame in 0..20 {
+            host.handle_local_input(frame as i32, local_handle).unwrap();
+            if frame < 15 {
+                host.handle_packet(net_input(net_handle, frame, 1)).unwrap();
+            }
+            host.update(&mut game).unwrap();
+        }
+
+        host.add_spectator();
+        let (start_frame, state) = host.spectator_start().unwrap();
+        assert_eq!(start_frame, 15);
+        assert_eq!(state, Some((0..15).sum::<i64>() + 15));
+        let mut spectator =
+            SpectatorClient::joining(vec![local_handle, net_handle], 4, 1, start_frame);
+        let mut spectator_game = Counter(state.unwrap());
+
+        // the late inputs weren't what was predicted, so the host rolls back
+        for frame in 15..20 {
+            host.handle_packet(net_input(net_handle, frame, 2)).unwrap();
+        }
+        for frame in 20..40 {
+            host.handle_local_input(frame as i32, local_handle).unwrap();
+            host.handle_packet(net_input(net_handle, frame, 2)).unwrap();
+            host.update(&mut game).unwrap();
+            for (spectator_handle, packet) in host.spectator_packets() {
+                if let Some(ack) = spectator.handle_packet(packet) {
+                    host.handle_spectator_packet(spectator_handle, ack).unwrap();
+                }
+            }
+            spectator.update(&mut spectator_game);
+        }
+
+        assert!(host.stats().rollbacks > 0);
+        let ran = spectator.current_frame();
+        assert!(ran > 30);
+        let expected = (0..ran as i64)
+            .map(|frame| frame + if frame < 15 { 1 } else { 2 })
+            .sum::<i64>();
+        assert_eq!(spectator_game.0, expected);
+    }
+
+    fn waiting_events(client: &mut NetcodeClient<i32, i64>) -> Vec<NetcodeEvent> {
+        client
+            .poll_events()
+            .filter(|event| matches!(event, NetcodeEvent::WaitingForPlayer(_)))
+            .collect()
+    }
+
+    #[test]
+    fn waiting_only_gets_reported_once_per_stall() {
+        let (mut client, local_handle) = client(0);
+        let net_handle = PlayerHandle(1);
+        let mut game = Counter(0);
+
+        client.handle_local_input(0, local_handle).unwrap();
+        for _ in 0..10 {
+            client.update(&mut game).unwrap();
+        }
+        assert_eq!(
+            waiting_events(&mut client),
+            vec![NetcodeEvent::WaitingForPlayer(net_handle)]
+        );
+        assert_eq!(client.stats().stalled_frames, 10);
+
+        client.handle_packet(net_input(net_handle, 0, 0)).unwrap();
+        client.update(&mut game).unwrap();
+        client.handle_local_input(0, local_handle).unwrap();
+        client.update(&mut game).unwrap();
+        client.update(&mut game).unwrap();
+        // a new stall gets reported again
+        assert_eq!(
+            waiting_events(&mut client),
+            vec![NetcodeEvent::WaitingForPlayer(net_handle)]
+        );
+    }
+
+    #[test]
+    fn unpolled_events_dont_pile_up() {
+        let (mut first, first_handle) = client(0);
+        let (mut second, second_handle) = client(1);
+        // a time sync event every frame
+        first.set_time_sync_interval(1);
+        let (mut first_game, mut second_game) = (Counter(0), Counter(0));
+        for _ in 0..MAX_QUEUED_EVENTS * 2 {
+            let to_second = first.handle_local_input(0, first_handle).unwrap();
+            let to_first = second.handle_local_input(0, second_handle).unwrap();
+            second.handle_packet(to_second.unwrap()).unwrap();
+            first.handle_packet(to_first.unwrap()).unwrap();
+            first.update(&mut first_game).unwrap();
+            second.update(&mut second_game).unwrap();
+        }
+
+        let events: Vec<_> = first.poll_events().collect();
+        assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+        // the oldest ones were thrown out first
+        assert!(!events.contains(&NetcodeEvent::Synchronized));
+        assert_eq!(first.current_frame(), MAX_QUEUED_EVENTS * 2);
+    }
+
+    #[test]
+    fn stats_follow_rollbacks_and_predictions_per_player() {
+        let (mut client, local_handle) = match_client(3, 0);
+        let (steady, wavering) = (PlayerHandle(1), PlayerHandle(2));
+        let mut game = Counter(0);
+
+        // nothing gets predicted until there's a whole rollback window behind us
+        let predicted_from = client.allowed_rollback() + 1;
+        for frame in 0..predicted_from {
+            client.handle_local_input(0, local_handle).unwrap();
+            client.handle_packet(net_input(steady, frame, 0)).unwrap();
+            client.handle_packet(net_input(wavering, frame, 0)).unwrap();
+            client.update(&mut game).unwrap();
+        }
+        for _ in 0..4 {
+            client.handle_local_input(0, local_handle).unwrap();
+            client.update(&mut game).unwrap();
+        }
+        let stats = client.stats().clone();
+        assert_eq!(stats.predictions[&steady].predictions, 4);
+        assert_eq!(stats.predictions[&wavering].predictions, 4);
+        assert_eq!(stats.rollbacks, 0);
+
+        for frame in predicted_from..predicted_from + 4 {
+            client.handle_packet(net_input(steady, frame, 0)).unwrap();
+            let input = if frame == predicted_from { 0 } else { 1 };
+            client
+                .handle_packet(net_input(wavering, frame, input))
+                .unwrap();
+        }
+        client.handle_local_input(0, local_handle).unwrap();
+        client.update(&mut game).unwrap();
+        let stats = client.stats();
+        assert_eq!(stats.rollbacks, 1);
+        // rolled back from the 4th predicted frame to the first wrong one
+        assert_eq!(stats.rollback_depths, vec![(3, 1)].into_iter().collect());
+        assert_eq!(stats.resimulated_frames, 3);
+        // the update after the rollback predicted one more frame
+        assert_eq!(
+            stats.predictions[&steady],
+            PredictionStats {
+                predictions: 5,
+                mispredictions: 0
+            }
+        );
+        assert_eq!(
+            stats.predictions[&wavering],
+            PredictionStats {
+                predictions: 5,
+                mispredictions: 3
+            }
+        );
+        assert_eq!(stats.stalled_frames, 0);
+
+        // without any more input we run out of rollback and stall
+        let stalled_from = client.current_frame();
+        for _ in 0..20 {
+            client.handle_local_input(0, local_handle).unwrap();
+            client.update(&mut game).unwrap();
+        }
+        let stats = client.stats();
+        assert!(stats.stalled_frames > 0);
+        assert_eq!(
+            client.current_frame() - stalled_from + stats.stalled_frames,
+            20
+        );
+        assert_eq!(stats.skipped_frames, 0);
+
+        client.reset_stats();
+        let stats = client.stats();
+        assert_eq!(stats.rollbacks, 0);
+        assert!(stats.rollback_depths.is_empty());
+        assert_eq!(stats.resimulated_frames, 0);
+        assert!(stats.predictions.is_empty());
+        assert_eq!(stats.stalled_frames, 0);
+    }
+
+    #[test]
+    fn running_ahead_skips_frames() {
+        let (mut client, local_handle) = client(0);
+        let net_handle = PlayerHandle(1);
+        client.set_time_sync_interval(4);
+        let mut game = Counter(0);
+
+        for _ in 0..40 {
+            let frame = client.current_frame();
+            client.handle_local_input(0, local_handle).unwrap();
+            // they're 4 frames behind us, so we should slow down by 2
+            client
+                .handle_packet(Packet::Inputs(
+                    net_handle,
+                    WireFrame::new(frame),
+                    -4,
+                    WireFrame::new(frame),
+                    PackedInputs(vec![0]),
+                    Vec::new(),
+                ))
+                .unwrap();
+            client.update(&mut game).unwrap();
+        }
+        let stats = client.stats();
+        assert!(stats.skipped_frames > 0);
+        assert_eq!(client.current_frame() + stats.skipped_frames, 40);
+        assert_eq!(stats.stalled_frames, 0);
+
+        client.reset_stats();
+        assert_eq!(client.stats().skipped_frames, 0);
+    }
 }
@@ -0,0 +1,365 @@
+use serde::de::{self, DeserializeOwned, Deserializer, SeqAccess, Visitor};
+use serde::ser::{self, SerializeTuple};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::io;
+
+// wire frames only cover 16 bits worth of frames, so no packet should ever hold more inputs than this
+const MAX_PACKED_INPUTS: usize = u16::MAX as usize;
+// the length goes over the wire as a u16
+const MAX_PACKED_BYTES: usize = u16::MAX as usize;
+
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // writes the lowest count bits of value
+    pub fn write_bits(&mut self, value: u64, count: u32) {
+        assert!(count <= 64, "Can't write more than 64 bits at once.");
+        for bit in 0..count {
+            if self.bit_len % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit_len % 8);
+            }
+            self.bit_len += 1;
+        }
+    }
+    pub fn write_bit(&mut self, value: bool) {
+        self.write_bits(value as u64, 1);
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// lets serde formats write straight into the bits
+impl io::Write for BitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            self.write_bits(u64::from(*byte), 8);
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, bit_pos: 0 }
+    }
+
+    // none if there aren't count bits left
+    pub fn read_bits(&mut self, count: u32) -> Option<u64> {
+        assert!(count <= 64, "Can't read more than 64 bits at once.");
+        if self.bit_pos + count as usize > self.bytes.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for bit in 0..count {
+            if (self.bytes[self.bit_pos / 8] >> (self.bit_pos % 8)) & 1 == 1 {
+                value |= 1 << bit;
+            }
+            self.bit_pos += 1;
+        }
+        Some(value)
+    }
+    pub fn read_bit(&mut self) -> Option<bool> {
+        self.read_bits(1).map(|bit| bit == 1)
+    }
+}
+
+impl io::Read for BitReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        for byte in buf.iter_mut() {
+            match self.read_bits(8) {
+                Some(value) => *byte = value as u8,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+// how inputs get packed into packets, the defaults just run the input through bincode,
+// so any input serde can handle works without any extra code
+pub trait NetInput: Serialize + DeserializeOwned + Default {
+    fn encode_bits(&self, writer: &mut BitWriter) {
+        // writing to a BitWriter can't fail
+        bincode::serialize_into(writer, self).unwrap();
+    }
+    fn decode_bits(reader: &mut BitReader<'_>) -> Option<Self> {
+        bincode::deserialize_from(reader).ok()
+    }
+    // a few inputs that between them touch every part of the encoding,
+    // the layout hash comes from how these encode, override this if the default input
+    // leaves parts of the encoding out, like an enum variant or an optional field
+    fn layout_probes() -> Vec<Self> {
+        vec![Self::default()]
+    }
+    // bump this when the encoding means something different, but the probes still encode the same
+    const LAYOUT_VERSION: u32 = 0;
+    // peers can only play together if this matches, so it's taken from the bits that actually
+    // go over the wire, instead of anything the compiler is free to change
+    fn layout_hash() -> u64
+    where
+        Self: Sized,
+    {
+        let mut writer = BitWriter::new();
+        for probe in Self::layout_probes() {
+            probe.encode_bits(&mut writer);
+        }
+        let hash = fnv1a(FNV_OFFSET, &Self::LAYOUT_VERSION.to_le_bytes());
+        let hash = fnv1a(hash, &(writer.bit_len() as u64).to_le_bytes());
+        fnv1a(hash, &writer.into_bytes())
+    }
+}
+
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// std's hashers aren't promised to stay the same between releases, this has to,
+// or peers built with different compilers would never match, so game checksums should use it too,
+// start from FNV_OFFSET and chain the hash through every call
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// 3 bits at a time, each followed by a bit saying if there's more
+fn write_count(writer: &mut BitWriter, mut value: usize) {
+    loop {
+        writer.write_bits((value & 0b111) as u64, 3);
+        value >>= 3;
+        writer.write_bit(value != 0);
+        if value == 0 {
+            break;
+        }
+    }
+}
+fn read_count(reader: &mut BitReader<'_>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        if shift > 16 {
+            // too big to be a real count
+            return None;
+        }
+        value |= (reader.read_bits(3)? as usize) << shift;
+        shift += 3;
+        if !reader.read_bit()? {
+            return Some(value);
+        }
+    }
+}
+
+// inputs that go over the wire bit packed, with repeated inputs run length encoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedInputs<Input>(pub Vec<Input>);
+
+impl<Input: NetInput + PartialEq> Serialize for PackedInputs<Input> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs: Vec<(&Input, usize)> = Vec::new();
+        for input in self.0.iter() {
+            match runs.last_mut() {
+                Some((last, count)) if *last == input => *count += 1,
+                _ => runs.push((input, 1)),
+            }
+        }
+
+        let mut writer = BitWriter::new();
+        write_count(&mut writer, runs.len());
+        for (input, count) in runs {
+            input.encode_bits(&mut writer);
+            write_count(&mut writer, count - 1);
+        }
+        // serialize_bytes would spend 8 bytes on the length, so it's a u16 followed by the bytes
+        let bytes = writer.into_bytes();
+        if bytes.len() > MAX_PACKED_BYTES {
+            return Err(ser::Error::custom("too many packed input bytes"));
+        }
+        let mut tuple = serializer.serialize_tuple(bytes.len() + 1)?;
+        tuple.serialize_element(&(bytes.len() as u16))?;
+        for byte in bytes.iter() {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, Input: NetInput + Clone> Deserialize<'de> for PackedInputs<Input> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_tuple(MAX_PACKED_BYTES + 1, BytesVisitor)?;
+        let mut reader = BitReader::new(&bytes);
+        let invalid = || de::Error::custom("invalid packed inputs");
+
+        let runs = read_count(&mut reader).ok_or_else(invalid)?;
+        let mut inputs = Vec::new();
+        for _ in 0..runs {
+            let input = Input::decode_bits(&mut reader).ok_or_else(invalid)?;
+            let count = read_count(&mut reader).ok_or_else(invalid)? + 1;
+            if inputs.len() + count > MAX_PACKED_INPUTS {
+                return Err(invalid());
+            }
+            inputs.resize(inputs.len() + count, input);
+        }
+        Ok(PackedInputs(inputs))
+    }
+}
+
+// reads back the length and then that many bytes
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "packed input bytes")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let len: u16 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        for idx in 0..len as usize {
+            let byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(idx + 1, &self))?;
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // like a stick direction, only needs 2 bits
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    struct Direction(i8);
+
+    impl NetInput for Direction {
+        fn encode_bits(&self, writer: &mut BitWriter) {
+            writer.write_bits((self.0 + 1) as u64, 2);
+        }
+        fn decode_bits(reader: &mut BitReader<'_>) -> Option<Self> {
+            match reader.read_bits(2)? {
+                bits @ 0..=2 => Some(Direction(bits as i8 - 1)),
+                _ => None,
+            }
+        }
+        fn layout_probes() -> Vec<Self> {
+            vec![Direction(-1), Direction(0), Direction(1)]
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    struct Buttons(u32);
+
+    impl NetInput for Buttons {}
+
+    fn round_trip<Input: NetInput + Clone + PartialEq>(
+        inputs: Vec<Input>,
+    ) -> (PackedInputs<Input>, usize) {
+        let bytes = bincode::serialize(&PackedInputs(inputs)).unwrap();
+        (bincode::deserialize(&bytes).unwrap(), bytes.len())
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), FNV_OFFSET);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            fnv1a(fnv1a(FNV_OFFSET, b"foo"), b"bar"),
+            fnv1a(FNV_OFFSET, b"foobar")
+        );
+    }
+
+    #[test]
+    fn bits_round_trip_across_bytes() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0xabcd, 16);
+        writer.write_bit(true);
+        assert_eq!(writer.bit_len(), 20);
+        let bytes = writer.into_bytes();
+        assert_eq!(bytes.len(), 3);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3), Some(0b101));
+        assert_eq!(reader.read_bits(16), Some(0xabcd));
+        assert_eq!(reader.read_bit(), Some(true));
+        // the padding at the end of the last byte
+        assert_eq!(reader.read_bits(4), Some(0));
+        assert_eq!(reader.read_bit(), None);
+    }
+
+    #[test]
+    fn packed_inputs_round_trip() {
+        let inputs: Vec<_> = [0, 0, 0, 1, 1, -1, 0, 0, 0, 0]
+            .iter()
+            .map(|direction| Direction(*direction))
+            .collect();
+
+        let (packed, len) = round_trip(inputs.clone());
+
+        assert_eq!(packed.0, inputs);
+        // 2 bytes of length, then the run count and 4 runs of 2 bits each with their counts in 4
+        assert_eq!(len, 6);
+    }
+
+    #[test]
+    fn default_encoding_round_trips() {
+        let inputs = vec![Buttons(0), Buttons(0x8000_0001), Buttons(0x8000_0001)];
+
+        let (packed, _) = round_trip(inputs.clone());
+
+        assert_eq!(packed.0, inputs);
+        assert_eq!(round_trip(Vec::<Buttons>::new()).0 .0, vec![]);
+    }
+
+    #[test]
+    fn long_runs_stay_small() {
+        let inputs = vec![Direction(1); 1000];
+
+        let (packed, len) = round_trip(inputs.clone());
+
+        assert_eq!(packed.0, inputs);
+        assert!(len <= 6, "took {} bytes", len);
+    }
+
+    #[test]
+    fn invalid_packed_inputs_are_rejected() {
+        let mut bytes = bincode::serialize(&PackedInputs(vec![Direction(0)])).unwrap();
+        // 3 isn't a direction
+        bytes[2] |= 0b11 << 4;
+        assert!(bincode::deserialize::<PackedInputs<Direction>>(&bytes).is_err());
+
+        // claims more bytes than there are
+        assert!(bincode::deserialize::<PackedInputs<Direction>>(&[4, 0, 1]).is_err());
+    }
+}
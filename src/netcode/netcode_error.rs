@@ -0,0 +1,127 @@
+use super::{InputStatus, PlayerHandle, SpectatorHandle};
+use crate::input_history::HistoryError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetcodeError {
+    UnknownPlayer(PlayerHandle),
+    PlayerAlreadyAdded(PlayerHandle),
+    NotLocalPlayer(PlayerHandle),
+    NotNetPlayer(PlayerHandle),
+    MissingSaveState(usize),
+    SaveStateStoreFull(usize),
+    MissingInput {
+        frame: usize,
+        player: PlayerHandle,
+    },
+    SyncTestWithNetPlayers,
+    InvalidSyncTestDistance,
+    MissingChecksum,
+    UnknownSpectator(SpectatorHandle),
+    PlayerDisconnected(PlayerHandle),
+    FrameOutOfRange,
+    RecordingStartedLate,
+    PlayerAddedWhileRecording,
+    InputOutsideWindow {
+        frame: usize,
+        player: PlayerHandle,
+    },
+    UnexpectedInputStatus {
+        frame: usize,
+        player: PlayerHandle,
+        status: InputStatus,
+    },
+}
+
+impl NetcodeError {
+    pub(super) fn from_history(error: HistoryError, player: PlayerHandle) -> Self {
+        match error {
+            HistoryError::OutsideWindow { frame } => {
+                NetcodeError::InputOutsideWindow { frame, player }
+            }
+            HistoryError::UnexpectedStatus { frame, status } => {
+                NetcodeError::UnexpectedInputStatus {
+                    frame,
+                    player,
+                    status,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for NetcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetcodeError::UnknownPlayer(player) => {
+                write!(f, "player {} was never added", player.id())
+            }
+            NetcodeError::PlayerAlreadyAdded(player) => {
+                write!(f, "player {} was already added", player.id())
+            }
+            NetcodeError::NotLocalPlayer(player) => {
+                write!(f, "player {} is not a local player", player.id())
+            }
+            NetcodeError::NotNetPlayer(player) => {
+                write!(f, "player {} is not a networked player", player.id())
+            }
+            NetcodeError::MissingSaveState(frame) => {
+                write!(f, "no save state for predicted frame {}", frame)
+            }
+            NetcodeError::SaveStateStoreFull(frame) => write!(
+                f,
+                "no room to save frame {} without losing a state that can still be loaded",
+                frame
+            ),
+            NetcodeError::MissingInput { frame, player } => {
+                write!(f, "no input for player {} on frame {}", player.id(), frame)
+            }
+            NetcodeError::SyncTestWithNetPlayers => {
+                write!(f, "sync testing can only be done with local players")
+            }
+            NetcodeError::InvalidSyncTestDistance => {
+                write!(f, "sync testing needs to rollback at least one frame")
+            }
+            NetcodeError::MissingChecksum => {
+                write!(
+                    f,
+                    "sync testing requires the game state to provide a checksum"
+                )
+            }
+            NetcodeError::UnknownSpectator(spectator) => {
+                write!(f, "spectator {} was never added", spectator.id())
+            }
+            NetcodeError::PlayerDisconnected(player) => {
+                write!(f, "player {} disconnected", player.id())
+            }
+            NetcodeError::FrameOutOfRange => {
+                write!(f, "received a frame too far from our current frame")
+            }
+            NetcodeError::RecordingStartedLate => {
+                write!(f, "recording has to start before the first frame")
+            }
+            NetcodeError::PlayerAddedWhileRecording => {
+                write!(f, "players can't be added while recording")
+            }
+            NetcodeError::InputOutsideWindow { frame, player } => write!(
+                f,
+                "input for player {} on frame {} doesn't fit in the input history",
+                player.id(),
+                frame
+            ),
+            NetcodeError::UnexpectedInputStatus {
+                frame,
+                player,
+                status,
+            } => write!(
+                f,
+                "input for player {} on frame {} was unexpectedly {:?}",
+                player.id(),
+                frame,
+                status
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NetcodeError {}
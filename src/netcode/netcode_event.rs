@@ -0,0 +1,18 @@
+use super::{Desync, PlayerHandle, SpectatorHandle, SyncTestFailure};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NetcodeEvent {
+    // every net player has been heard from at least once
+    Synchronized,
+    RollbackPerformed { from: usize, to: usize },
+    WaitingForPlayer(PlayerHandle),
+    // how many frames ahead of our peers we are, positive means we should slow down
+    TimeSync { frames_ahead: f32 },
+    PeerInterrupted(PlayerHandle),
+    PeerResumed(PlayerHandle),
+    PeerDisconnected(PlayerHandle),
+    Desync(Desync),
+    SyncTestFailed(SyncTestFailure),
+    // fell too far behind to catch up, so we stopped sending them inputs
+    SpectatorDropped(SpectatorHandle),
+}
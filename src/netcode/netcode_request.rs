@@ -0,0 +1,19 @@
+use super::Packet;
+
+// what the game needs to do, in order, to run an update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetcodeRequest {
+    // load the state from NetcodeClient::take_state
+    LoadState(usize),
+    // save the state from right before this frame runs with NetcodeClient::save_state
+    SaveState(usize),
+    // run the frame with the inputs from NetcodeClient::inputs
+    AdvanceFrame { frame: usize, is_resimulating: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateRequests<Input> {
+    pub requests: Vec<NetcodeRequest>,
+    // has to be sent to the other players
+    pub packet: Option<Packet<Input>>,
+}
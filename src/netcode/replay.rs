@@ -0,0 +1,288 @@
+use super::{InputSet, InputStatus, PlayerHandle, RollbackableGameState};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplaySettings {
+    pub held_input_count: usize,
+    pub input_delay: usize,
+    pub allowed_rollback: usize,
+}
+
+// every confirmed input from the start of a session, stored per player in InputSet order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Replay<Input> {
+    pub settings: ReplaySettings,
+    pub players: Vec<PlayerHandle>,
+    pub inputs: Vec<Vec<Input>>,
+}
+
+impl<Input> Replay<Input> {
+    // bump this whenever the layout of a replay changes
+    pub const VERSION: u32 = 1;
+
+    pub(super) fn new(settings: ReplaySettings, players: Vec<PlayerHandle>) -> Self {
+        Self {
+            settings,
+            inputs: players.iter().map(|_| Vec::new()).collect(),
+            players,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.inputs.first().map(|inputs| inputs.len()).unwrap_or(0)
+    }
+}
+
+impl<Input: Serialize> Replay<Input> {
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        bincode::serialize_into(&mut writer, &Self::VERSION)
+            .and_then(|_| bincode::serialize_into(&mut writer, self))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "bincode serialization failed while saving a replay",
+                )
+            })
+    }
+}
+
+impl<Input: DeserializeOwned> Replay<Input> {
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version: u32 = bincode::deserialize_from(&mut reader).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bincode deserialization failed while loading a replay version",
+            )
+        })?;
+        if version != Self::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "replay version {} doesn't match supported version {}",
+                    version,
+                    Self::VERSION
+                ),
+            ));
+        }
+        let replay: Self = bincode::deserialize_from(&mut reader).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bincode deserialization failed while loading a replay",
+            )
+        })?;
+        // playback assumes every player has an input on every frame
+        let frame_count = replay.frame_count();
+        if replay.inputs.len() != replay.players.len()
+            || replay
+                .inputs
+                .iter()
+                .any(|inputs| inputs.len() != frame_count)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "replay doesn't have the same frames of input for every player",
+            ));
+        }
+        Ok(replay)
+    }
+}
+
+pub struct ReplayPlayer<Input> {
+    replay: Replay<Input>,
+    current_frame: usize,
+    paused: bool,
+    speed: usize,
+}
+
+impl<Input: Clone> ReplayPlayer<Input> {
+    pub fn new(replay: Replay<Input>) -> Self {
+        Self {
+            replay,
+            current_frame: 0,
+            paused: false,
+            speed: 1,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay<Input> {
+        &self.replay
+    }
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+    pub fn is_finished(&self) -> bool {
+        self.current_frame >= self.replay.frame_count()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+    pub fn set_paused(&mut self, value: bool) {
+        self.paused = value;
+    }
+    pub fn speed(&self) -> usize {
+        self.speed
+    }
+    // how many frames each update runs, anything above 1 is fast forwarding
+    pub fn set_speed(&mut self, value: usize) {
+        self.speed = value.max(1);
+    }
+
+    // runs a single frame, even when paused, returns false if the replay is over
+    pub fn step<Game: RollbackableGameState<Input = Input>>(&mut self, game: &mut Game) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+
+        let first_frame =
+            (self.current_frame + 1).saturating_sub(self.replay.settings.held_input_count);
+        game.advance_frame(InputSet {
+            frame: self.current_frame,
+            is_resimulating: false,
+            inputs: self
+                .replay
+                .inputs
+                .iter()
+                .map(|inputs| &inputs[first_frame..=self.current_frame])
+                .collect(),
+            players: &self.replay.players,
+            statuses: vec![InputStatus::Confirmed; self.replay.players.len()],
+        });
+        self.current_frame += 1;
+        true
+    }
+
+    // returns how many frames were run
+    pub fn update<Game: RollbackableGameState<Input = Input>>(&mut self, game: &mut Game) -> usize {
+        if self.paused {
+            return 0;
+        }
+        (0..self.speed).take_while(|_| self.step(game)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netcode::NetcodeClient;
+
+    // remembers the held inputs of every frame it ran
+    #[derive(Default)]
+    struct Recorder {
+        frames: Vec<Vec<Vec<i32>>>,
+    }
+
+    impl RollbackableGameState for Recorder {
+        type Input = i32;
+        type SavedState = ();
+        fn advance_frame(&mut self, input: InputSet<'_, Self::Input>) {
+            assert_eq!(input.frame, self.frames.len());
+            self.frames
+                .push(input.inputs.iter().map(|inputs| inputs.to_vec()).collect());
+        }
+        fn save_state(&self) -> Self::SavedState {}
+        fn load_state(&mut self, _: Self::SavedState) {}
+    }
+
+    fn replay(frames: i32) -> Replay<i32> {
+        let mut client = NetcodeClient::<i32, ()>::new(2);
+        let players = vec![
+            client.add_local_player(0).unwrap(),
+            client.add_local_player(1).unwrap(),
+        ];
+        let mut replay = Replay::new(
+            ReplaySettings {
+                held_input_count: 2,
+                input_delay: 0,
+                allowed_rollback: 0,
+            },
+            players,
+        );
+        replay.inputs = vec![(0..frames).collect(), (0..frames).map(|x| -x).collect()];
+        replay
+    }
+
+    #[test]
+    fn playback_hands_out_the_held_inputs() {
+        let mut player = ReplayPlayer::new(replay(3));
+        let mut game = Recorder::default();
+
+        while player.step(&mut game) {}
+
+        assert!(player.is_finished());
+        assert_eq!(
+            game.frames,
+            vec![
+                vec![vec![0], vec![0]],
+                vec![vec![0, 1], vec![0, -1]],
+                vec![vec![1, 2], vec![-1, -2]],
+            ]
+        );
+    }
+
+    #[test]
+    fn pausing_and_fast_forwarding() {
+        let mut player = ReplayPlayer::new(replay(10));
+        let mut game = Recorder::default();
+
+        player.set_paused(true);
+        assert_eq!(player.update(&mut game), 0);
+        // stepping still works while paused
+        assert!(player.step(&mut game));
+        assert_eq!(player.current_frame(), 1);
+
+        player.set_paused(false);
+        player.set_speed(4);
+        assert_eq!(player.update(&mut game), 4);
+        assert_eq!(player.update(&mut game), 4);
+        // only one frame left
+        assert_eq!(player.update(&mut game), 1);
+        assert_eq!(player.update(&mut game), 0);
+
+        player.set_speed(0);
+        assert_eq!(player.speed(), 1);
+    }
+
+    #[test]
+    fn replays_round_trip() {
+        let replay = replay(5);
+        let mut data = Vec::new();
+        replay.save(&mut data).unwrap();
+
+        let loaded = Replay::<i32>::load(&data[..]).unwrap();
+
+        assert_eq!(loaded.settings, replay.settings);
+        assert_eq!(loaded.players, replay.players);
+        assert_eq!(loaded.inputs, replay.inputs);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut data = Vec::new();
+        replay(5).save(&mut data).unwrap();
+        data[0] += 1;
+
+        let error = Replay::<i32>::load(&data[..]).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn malformed_replays_are_rejected() {
+        let mut missing_player = replay(5);
+        missing_player.inputs.pop();
+        let mut missing_frames = replay(5);
+        missing_frames.inputs[1].truncate(3);
+
+        for malformed in [missing_player, missing_frames].iter() {
+            let mut data = Vec::new();
+            malformed.save(&mut data).unwrap();
+
+            let error = Replay::<i32>::load(&data[..]).unwrap_err();
+
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use super::PlayerHandle;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PredictionStats {
+    pub predictions: usize,
+    pub mispredictions: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RollbackStats {
+    pub rollbacks: usize,
+    // how many frames each rollback went back, mapped to how many times that happened
+    pub rollback_depths: BTreeMap<usize, usize>,
+    pub resimulated_frames: usize,
+    pub predictions: HashMap<PlayerHandle, PredictionStats>,
+    // frames we couldn't run because we were waiting on input
+    pub stalled_frames: usize,
+    // frames we didn't run to let the other side catch up
+    pub skipped_frames: usize,
+}
+
+impl RollbackStats {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(super) fn add_rollback(&mut self, depth: usize) {
+        self.rollbacks += 1;
+        *self.rollback_depths.entry(depth).or_insert(0) += 1;
+        self.resimulated_frames += depth;
+    }
+    pub(super) fn add_prediction(&mut self, player: PlayerHandle) {
+        self.predictions.entry(player).or_default().predictions += 1;
+    }
+    pub(super) fn add_misprediction(&mut self, player: PlayerHandle) {
+        self.predictions.entry(player).or_default().mispredictions += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollbacks_are_bucketed_by_depth() {
+        let mut stats = RollbackStats::default();
+        stats.add_rollback(2);
+        stats.add_rollback(5);
+        stats.add_rollback(2);
+
+        assert_eq!(stats.rollbacks, 3);
+        assert_eq!(
+            stats.rollback_depths,
+            vec![(2, 2), (5, 1)].into_iter().collect()
+        );
+        assert_eq!(stats.resimulated_frames, 9);
+    }
+
+    #[test]
+    fn predictions_are_kept_per_player() {
+        let mut stats = RollbackStats::default();
+        let (first, second) = (PlayerHandle(0), PlayerHandle(1));
+        stats.add_prediction(first);
+        stats.add_prediction(first);
+        stats.add_misprediction(first);
+        stats.add_prediction(second);
+
+        assert_eq!(
+            stats.predictions[&first],
+            PredictionStats {
+                predictions: 2,
+                mispredictions: 1
+            }
+        );
+        assert_eq!(
+            stats.predictions[&second],
+            PredictionStats {
+                predictions: 1,
+                mispredictions: 0
+            }
+        );
+    }
+
+    #[test]
+    fn reset_clears_everything() {
+        let mut stats = RollbackStats::default();
+        stats.add_rollback(3);
+        stats.add_prediction(PlayerHandle(0));
+        stats.add_misprediction(PlayerHandle(0));
+        stats.stalled_frames = 4;
+        stats.skipped_frames = 2;
+
+        stats.reset();
+        assert_eq!(stats.rollbacks, 0);
+        assert!(stats.rollback_depths.is_empty());
+        assert_eq!(stats.resimulated_frames, 0);
+        assert!(stats.predictions.is_empty());
+        assert_eq!(stats.stalled_frames, 0);
+        assert_eq!(stats.skipped_frames, 0);
+    }
+}
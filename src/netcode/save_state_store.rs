@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+// where NetcodeClient keeps the states it might need to rollback to,
+// implement this to pool, compress, or diff your save states
+pub trait SaveStateStore<GameState> {
+    // replaces any state already saved for the frame, hands the state back if there's no room
+    // for it without throwing out another state that might still be loaded
+    fn save(&mut self, frame: usize, state: GameState) -> Result<(), GameState>;
+    // removes the state so it can be loaded
+    fn take(&mut self, frame: usize) -> Option<GameState>;
+    fn contains(&self, frame: usize) -> bool;
+    // nothing before frame will ever be loaded again
+    fn drop_before(&mut self, frame: usize);
+}
+
+impl<GameState> SaveStateStore<GameState> for HashMap<usize, GameState> {
+    fn save(&mut self, frame: usize, state: GameState) -> Result<(), GameState> {
+        self.insert(frame, state);
+        Ok(())
+    }
+    fn take(&mut self, frame: usize) -> Option<GameState> {
+        self.remove(&frame)
+    }
+    fn contains(&self, frame: usize) -> bool {
+        self.contains_key(&frame)
+    }
+    fn drop_before(&mut self, frame: usize) {
+        self.retain(|saved, _| *saved >= frame);
+    }
+}
+
+// never allocates after being created, but only holds capacity frames,
+// so it needs room for at least allowed_rollback + 1 frames (or the sync test distance + 1),
+// saving fails instead of overwriting a state that hasn't been dropped yet
+#[derive(Debug)]
+pub struct RingBufferStore<GameState> {
+    states: Vec<Option<(usize, GameState)>>,
+}
+
+impl<GameState> RingBufferStore<GameState> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "Ring buffer store needs room for a state.");
+        Self {
+            states: (0..capacity).map(|_| None).collect(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.states.len()
+    }
+
+    fn slot(&self, frame: usize) -> usize {
+        frame % self.states.len()
+    }
+}
+
+impl<GameState> SaveStateStore<GameState> for RingBufferStore<GameState> {
+    fn save(&mut self, frame: usize, state: GameState) -> Result<(), GameState> {
+        let slot = self.slot(frame);
+        match self.states[slot] {
+            Some((saved, _)) if saved != frame => Err(state),
+            _ => {
+                self.states[slot] = Some((frame, state));
+                Ok(())
+            }
+        }
+    }
+    fn take(&mut self, frame: usize) -> Option<GameState> {
+        let slot = self.slot(frame);
+        match self.states[slot] {
+            Some((saved, _)) if saved == frame => self.states[slot].take().map(|(_, state)| state),
+            _ => None,
+        }
+    }
+    fn contains(&self, frame: usize) -> bool {
+        match self.states[self.slot(frame)] {
+            Some((saved, _)) => saved == frame,
+            None => false,
+        }
+    }
+    fn drop_before(&mut self, frame: usize) {
+        for state in self.states.iter_mut() {
+            if let Some((saved, _)) = state {
+                if *saved < frame {
+                    *state = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_store_wraps_around() {
+        let mut store = RingBufferStore::with_capacity(3);
+        for frame in 0..3 {
+            store.save(frame, frame * 10).unwrap();
+        }
+        store.drop_before(2);
+        assert!(!store.contains(0));
+
+        store.save(3, 30).unwrap();
+        store.save(4, 40).unwrap();
+
+        assert_eq!(store.take(2), Some(20));
+        assert_eq!(store.take(4), Some(40));
+        // it was taken already
+        assert_eq!(store.take(4), None);
+        assert!(store.contains(3));
+        assert!(!store.contains(6));
+    }
+
+    #[test]
+    fn ring_buffer_store_keeps_states_that_werent_dropped() {
+        let mut store = RingBufferStore::with_capacity(2);
+        store.save(0, 0).unwrap();
+        store.save(1, 10).unwrap();
+
+        assert_eq!(store.save(2, 20), Err(20));
+        // saving over the same frame is fine
+        assert_eq!(store.save(1, 11), Ok(()));
+        assert_eq!(store.take(0), Some(0));
+        assert_eq!(store.save(2, 20), Ok(()));
+    }
+
+    #[test]
+    fn hash_map_store_only_keeps_later_frames() {
+        let mut store = HashMap::new();
+        for frame in 0..5 {
+            store.save(frame, frame).unwrap();
+        }
+
+        store.drop_before(3);
+
+        assert!(!store.contains(2));
+        assert_eq!(SaveStateStore::take(&mut store, 3), Some(3));
+        assert!(store.contains(4));
+    }
+}
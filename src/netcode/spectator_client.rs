@@ -0,0 +1,338 @@
+use super::{InputSet, InputStatus, Packet, PlayerHandle, RollbackableGameState, WireFrame};
+use crate::input_history::{InputHistory, LocalHistory};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectatorStatus {
+    // waiting for enough frames to arrive before we start running again
+    Buffering,
+    Advanced,
+    // ran out of frames, so we go back to buffering
+    Starved,
+}
+
+// drives a game purely from confirmed inputs, so it never has to rollback
+pub struct SpectatorClient<Input> {
+    handles: Vec<PlayerHandle>,
+    players: Vec<LocalHistory<Input>>,
+    current_frame: usize,
+    received_frame: usize,
+    held_input_count: usize,
+    buffer_size: usize,
+    buffering: bool,
+}
+
+impl<Input: Clone + Default> SpectatorClient<Input> {
+    // players has to match the players the host added, in the same order
+    pub fn new(players: Vec<PlayerHandle>, held_input_count: usize, buffer_size: usize) -> Self {
+        Self::joining(players, held_input_count, buffer_size, 0)
+    }
+    // for joining a match that already started, start_frame comes from
+    // NetcodeClient::spectator_start, along with the state the game has to start out with
+    pub fn joining(
+        players: Vec<PlayerHandle>,
+        held_input_count: usize,
+        buffer_size: usize,
+        start_frame: usize,
+    ) -> Self {
+        // the host sends the held inputs leading up to the start frame too
+        let first_frame = (start_frame + 1).saturating_sub(held_input_count);
+        Self {
+            players: players
+                .iter()
+                .map(|_| {
+                    LocalHistory::starting_at(
+                        Self::capacity(held_input_count, buffer_size),
+                        first_frame,
+                    )
+                })
+                .collect(),
+            handles: players,
+            current_frame: start_frame,
+            received_frame: first_frame,
+            held_input_count,
+            buffer_size,
+            buffering: true,
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+    pub fn set_buffer_size(&mut self, value: usize) {
+        self.buffer_size = value;
+
+        let capacity = Self::capacity(self.held_input_count, value);
+        for player in self.players.iter_mut() {
+            player.set_capacity(capacity);
+        }
+    }
+    fn capacity(held_input_count: usize, buffer_size: usize) -> usize {
+        held_input_count * 2 + buffer_size
+    }
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+    pub fn buffered_frames(&self) -> usize {
+        self.received_frame.saturating_sub(self.current_frame)
+    }
+
+    // must return to sender
+    pub fn handle_packet(&mut self, packet: Packet<Input>) -> Option<Packet<Input>> {
+        if let Packet::Spectate(start_frame, inputs) = packet {
+            let start_frame = start_frame.resolve(self.received_frame)?;
+            // not the players we were set up with, so there's nothing we can do with it
+            if inputs.len() != self.players.len() {
+                return None;
+            }
+            let frames = inputs
+                .iter()
+                .map(|inputs| inputs.0.len())
+                .min()
+                .unwrap_or(0);
+            for idx in 0..frames {
+                // anything other than the next frame is either a duplicate or arrived out of order
+                // and will be resent, because we only ack what we have
+                if start_frame + idx != self.received_frame {
+                    continue;
+                }
+                // every player holds the same frames, so either they all fit or none of them do
+                // if they don't we're behind, and the host will resend them once we ack
+                let added = self
+                    .players
+                    .iter_mut()
+                    .zip(inputs.iter())
+                    .all(|(player, inputs)| player.add_input(inputs.0[idx].clone()).is_ok());
+                if !added {
+                    break;
+                }
+                self.received_frame += 1;
+            }
+            Some(Packet::SpectatorAck(WireFrame::new(self.received_frame)))
+        } else {
+            None
+        }
+    }
+
+    pub fn update<Game: RollbackableGameState<Input = Input>>(
+        &mut self,
+        game: &mut Game,
+    ) -> SpectatorStatus {
+        if self.buffering {
+            if self.buffered_frames() < self.buffer_size.max(1) {
+                return SpectatorStatus::Buffering;
+            }
+            self.buffering = false;
+        }
+
+        if self.buffered_frames() == 0 {
+            self.buffering = true;
+            return SpectatorStatus::Starved;
+        }
+
+        game.advance_frame(InputSet {
+            frame: self.current_frame,
+            is_resimulating: false,
+            inputs: self
+                .players
+                .iter()
+                .map(|player| {
+                    player
+                        .request_inputs(self.current_frame, self.held_input_count)
+                        .map_or(&[][..], |window| window.inputs)
+                })
+                .collect(),
+            players: &self.handles,
+            statuses: vec![InputStatus::Confirmed; self.handles.len()],
+        });
+        self.current_frame += 1;
+
+        let clear_target = self.current_frame.saturating_sub(self.held_input_count);
+        for player in self.players.iter_mut() {
+            // local histories are always confirmed, so cleaning them can't fail
+            player.clean(clear_target).ok();
+        }
+
+        SpectatorStatus::Advanced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netcode::{NetcodeClient, NetcodeError, NetcodeEvent};
+    use std::collections::BTreeMap;
+
+    const HELD_INPUTS: usize = 3;
+
+    // keeps a running total of the inputs, and remembers the input windows every frame was run with
+    #[derive(Default)]
+    struct WindowGame {
+        total: i64,
+        windows: BTreeMap<usize, (i64, Vec<Vec<i32>>)>,
+    }
+
+    impl WindowGame {
+        fn starting_from(total: i64) -> Self {
+            Self {
+                total,
+                windows: BTreeMap::new(),
+            }
+        }
+    }
+
+    impl RollbackableGameState for WindowGame {
+        type Input = i32;
+        type SavedState = i64;
+        fn advance_frame(&mut self, input: InputSet<'_, Self::Input>) {
+            for inputs in input.inputs.iter() {
+                self.total += *inputs.last().unwrap() as i64;
+            }
+            let windows = input.inputs.iter().map(|inputs| inputs.to_vec()).collect();
+            self.windows.insert(input.frame, (self.total, windows));
+        }
+        fn save_state(&self) -> Self::SavedState {
+            self.total
+        }
+        fn load_state(&mut self, load: Self::SavedState) {
+            self.total = load;
+        }
+    }
+
+    struct Host {
+        client: NetcodeClient<i32, i64>,
+        game: WindowGame,
+        players: Vec<PlayerHandle>,
+    }
+
+    impl Host {
+        fn new() -> Self {
+            let mut client = NetcodeClient::new(HELD_INPUTS);
+            let players = vec![
+                client.add_local_player(0).unwrap(),
+                client.add_local_player(1).unwrap(),
+            ];
+            Self {
+                client,
+                game: WindowGame::default(),
+                players,
+            }
+        }
+
+        fn advance(&mut self) -> Result<(), NetcodeError> {
+            let frame = self.client.current_frame() as i32;
+            for (idx, player) in self.players.iter().enumerate() {
+                self.client
+                    .handle_local_input(frame * 2 + idx as i32, *player)?;
+            }
+            self.client.update(&mut self.game)?;
+            Ok(())
+        }
+
+        fn send_to(&mut self, spectator: &mut SpectatorClient<i32>) {
+            for (handle, packet) in self.client.spectator_packets() {
+                if let Some(ack) = spectator.handle_packet(packet) {
+                    self.client.handle_spectator_packet(handle, ack).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spectators_can_join_mid_match() {
+        let mut host = Host::new();
+        for _ in 0..60 {
+            host.advance().unwrap();
+        }
+
+        host.client.add_spectator();
+        // every input is local, so nothing can be rolled back and we start from the current state
+        let (start_frame, state) = host.client.spectator_start().unwrap();
+        assert_eq!(start_frame, host.client.current_frame());
+        assert_eq!(state, None);
+        let mut spectator =
+            SpectatorClient::joining(host.players.clone(), HELD_INPUTS, 2, start_frame);
+        let mut game = WindowGame::starting_from(host.game.total);
+
+        let mut advanced = 0;
+        for _ in 0..30 {
+            host.advance().unwrap();
+            host.send_to(&mut spectator);
+            if spectator.update(&mut game) == SpectatorStatus::Advanced {
+                advanced += 1;
+            }
+        }
+
+        assert!(advanced > 20);
+        assert_eq!(*game.windows.keys().next().unwrap(), start_frame);
+        for (frame, ran) in game.windows.iter() {
+            assert_eq!(ran, &host.game.windows[frame]);
+            assert!(ran.1.iter().all(|window| window.len() == HELD_INPUTS));
+        }
+    }
+
+    #[test]
+    fn silent_spectators_only_hold_back_cleaning_so_far() {
+        let mut host = Host::new();
+        let silent = host.client.add_spectator();
+        let watching = host.client.add_spectator();
+        let mut spectator = SpectatorClient::new(host.players.clone(), HELD_INPUTS, 2);
+        let mut game = WindowGame::default();
+
+        // the histories would fill up if the silent spectator held them back forever
+        for _ in 0..200 {
+            host.advance().unwrap();
+            for (_, packet) in host
+                .client
+                .spectator_packets()
+                .into_iter()
+                .filter(|(handle, _)| *handle == watching)
+            {
+                if let Some(ack) = spectator.handle_packet(packet) {
+                    host.client.handle_spectator_packet(watching, ack).unwrap();
+                }
+            }
+            spectator.update(&mut game);
+        }
+
+        assert_eq!(
+            host.client.spectator_next_frame(silent),
+            Err(NetcodeError::UnknownSpectator(silent))
+        );
+        let dropped: Vec<_> = host
+            .client
+            .poll_events()
+            .filter(|event| matches!(event, NetcodeEvent::SpectatorDropped(_)))
+            .collect();
+        assert_eq!(dropped, vec![NetcodeEvent::SpectatorDropped(silent)]);
+        // the spectator that kept up never missed a frame
+        assert!(spectator.current_frame() > 190);
+        assert_eq!(game.windows[&150], host.game.windows[&150]);
+    }
+
+    #[test]
+    fn spectators_wait_for_their_buffer() {
+        let mut host = Host::new();
+        host.client.add_spectator();
+        let mut spectator = SpectatorClient::new(host.players.clone(), HELD_INPUTS, 4);
+        let mut game = WindowGame::default();
+
+        let mut statuses = Vec::new();
+        for _ in 0..8 {
+            host.advance().unwrap();
+            host.send_to(&mut spectator);
+            statuses.push(spectator.update(&mut game));
+        }
+
+        assert_eq!(statuses[0], SpectatorStatus::Buffering);
+        assert!(statuses.contains(&SpectatorStatus::Advanced));
+
+        // nothing new arrives, so the spectator runs out of frames and buffers again
+        let mut status = SpectatorStatus::Advanced;
+        while status == SpectatorStatus::Advanced {
+            status = spectator.update(&mut game);
+        }
+        assert_eq!(status, SpectatorStatus::Starved);
+        assert_eq!(spectator.update(&mut game), SpectatorStatus::Buffering);
+        assert_eq!(game.windows[&0].1, vec![vec![0], vec![1]]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+// keeps a running window of how far ahead we think we are of our peers, and how far ahead
+// they think they are of us, averaging both cancels out most of the error in either estimate
+#[derive(Debug)]
+pub struct TimeSync {
+    local_advantages: VecDeque<i32>,
+    remote_advantages: VecDeque<i32>,
+    window_size: usize,
+}
+
+impl TimeSync {
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            local_advantages: VecDeque::with_capacity(window_size),
+            remote_advantages: VecDeque::with_capacity(window_size),
+            window_size,
+        }
+    }
+
+    fn push(window: &mut VecDeque<i32>, window_size: usize, advantage: i32) {
+        window.push_back(advantage);
+        while window.len() > window_size {
+            window.pop_front();
+        }
+    }
+    fn average(window: &VecDeque<i32>) -> f32 {
+        if window.is_empty() {
+            0.0
+        } else {
+            window.iter().sum::<i32>() as f32 / window.len() as f32
+        }
+    }
+
+    pub fn add_local_advantage(&mut self, advantage: i32) {
+        Self::push(&mut self.local_advantages, self.window_size, advantage);
+    }
+    pub fn add_remote_advantage(&mut self, advantage: i32) {
+        Self::push(&mut self.remote_advantages, self.window_size, advantage);
+    }
+
+    pub fn local_advantage(&self) -> f32 {
+        Self::average(&self.local_advantages)
+    }
+
+    // how many frames we should slow down by, negative if we're the ones behind
+    pub fn recommended_frame_delay(&self) -> f32 {
+        (Self::average(&self.local_advantages) - Self::average(&self.remote_advantages)) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_do_without_samples() {
+        let time_sync = TimeSync::new(4);
+
+        assert_eq!(time_sync.local_advantage(), 0.0);
+        assert_eq!(time_sync.recommended_frame_delay(), 0.0);
+    }
+
+    #[test]
+    fn both_sides_get_averaged() {
+        let mut time_sync = TimeSync::new(4);
+        for advantage in [2, 4].iter() {
+            time_sync.add_local_advantage(*advantage);
+        }
+        time_sync.add_remote_advantage(-3);
+        time_sync.add_remote_advantage(-1);
+
+        assert_eq!(time_sync.local_advantage(), 3.0);
+        // we're 3 frames ahead, they think they're 2 behind, so we slow down by the average
+        assert_eq!(time_sync.recommended_frame_delay(), 2.5);
+    }
+
+    #[test]
+    fn behind_is_negative() {
+        let mut time_sync = TimeSync::new(4);
+        time_sync.add_local_advantage(-2);
+        time_sync.add_remote_advantage(2);
+
+        assert_eq!(time_sync.recommended_frame_delay(), -2.0);
+    }
+
+    #[test]
+    fn old_samples_fall_out_of_the_window() {
+        let mut time_sync = TimeSync::new(2);
+        for advantage in [10, 10, 1, 3].iter() {
+            time_sync.add_local_advantage(*advantage);
+        }
+
+        assert_eq!(time_sync.local_advantage(), 2.0);
+    }
+}
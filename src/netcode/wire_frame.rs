@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+// frames only go over the wire as their low 16 bits, the receiver recovers the full frame by
+// picking the one closest to its own current frame, which works as long as the peers are within
+// ~9 minutes (at 60 FPS) of each other, rather than 2^16 frames into the session
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireFrame(u16);
+
+impl WireFrame {
+    pub fn new(frame: usize) -> Self {
+        WireFrame(frame as u16)
+    }
+
+    pub fn resolve(self, reference: usize) -> Option<usize> {
+        let offset = self.0.wrapping_sub(reference as u16) as i16;
+        if offset >= 0 {
+            reference.checked_add(offset as usize)
+        } else {
+            reference.checked_sub(-(offset as i32) as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_near_the_reference_resolve() {
+        assert_eq!(WireFrame::new(100).resolve(90), Some(100));
+        assert_eq!(WireFrame::new(90).resolve(100), Some(90));
+        assert_eq!(WireFrame::new(100).resolve(100), Some(100));
+    }
+
+    #[test]
+    fn frames_resolve_across_the_16_bit_wraparound() {
+        let frame = 3 * 65536 + 5;
+        assert_eq!(WireFrame::new(frame).resolve(frame - 10), Some(frame));
+        assert_eq!(WireFrame::new(frame - 10).resolve(frame), Some(frame - 10));
+        assert_eq!(WireFrame::new(65535).resolve(65536 + 2), Some(65535));
+    }
+
+    #[test]
+    fn frames_before_the_first_frame_dont_resolve() {
+        assert_eq!(WireFrame::new(65530).resolve(3), None);
+    }
+
+    #[test]
+    fn only_half_the_range_resolves_ahead() {
+        let reference = 40000;
+        assert_eq!(
+            WireFrame::new(reference + 32767).resolve(reference),
+            Some(reference + 32767)
+        );
+        // anything further is assumed to be from the past
+        assert_eq!(
+            WireFrame::new(reference + 32768).resolve(reference),
+            Some(reference - 32768)
+        );
+    }
+}
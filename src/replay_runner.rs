@@ -0,0 +1,76 @@
+use crate::game::{GameInput, GameState};
+use crate::netcode::{Replay, ReplayPlayer};
+use crate::rollback_runner::NETCODE_SETTINGS;
+use ggez::event::EventHandler;
+use ggez::event::{KeyCode, KeyMods};
+use ggez::{graphics, Context, GameResult};
+
+// plays back a saved match, space pauses, right steps a frame, up and down change the speed
+pub struct ReplayRunner {
+    current_state: GameState,
+    player: ReplayPlayer<GameInput>,
+}
+
+impl ReplayRunner {
+    pub fn new(ctx: &mut Context, replay: Replay<GameInput>) -> ReplayRunner {
+        ReplayRunner {
+            current_state: GameState::new(ctx),
+            player: ReplayPlayer::new(replay),
+        }
+    }
+}
+
+impl EventHandler for ReplayRunner {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        while ggez::timer::check_update_time(ctx, NETCODE_SETTINGS.tick_rate) {
+            self.player.update(&mut self.current_state);
+        }
+        Ok(())
+    }
+
+    fn key_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        keycode: KeyCode,
+        _keymod: KeyMods,
+        repeat: bool,
+    ) {
+        if !repeat {
+            match keycode {
+                KeyCode::Space => self.player.set_paused(!self.player.paused()),
+                KeyCode::Right => {
+                    self.player.step(&mut self.current_state);
+                }
+                KeyCode::Up => self.player.set_speed(self.player.speed() + 1),
+                KeyCode::Down => self.player.set_speed(self.player.speed() - 1),
+                _ => (),
+            }
+        }
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        graphics::clear(ctx, graphics::BLACK);
+        self.current_state.draw(ctx, 100.0)?;
+        graphics::draw(
+            ctx,
+            &graphics::Text::new(format!(
+                "Frame: f{}/f{}",
+                self.player.current_frame(),
+                self.player.replay().frame_count()
+            )),
+            graphics::DrawParam::default().dest([30.0, 200.0]),
+        )?;
+        graphics::draw(
+            ctx,
+            &graphics::Text::new(if self.player.paused() {
+                "Paused".to_owned()
+            } else if self.player.is_finished() {
+                "Finished".to_owned()
+            } else {
+                format!("Speed: {}x", self.player.speed())
+            }),
+            graphics::DrawParam::default().dest([30.0, 250.0]),
+        )?;
+        graphics::present(ctx)
+    }
+}
@@ -1,19 +1,30 @@
 use crate::game::{GameInput, GameState};
-use crate::net_client::TestNetClient;
-use crate::netcode::{self, NetcodeClient, PlayerHandle};
+use crate::net_client::{NetClient, NetcodeSettings};
+use crate::netcode::{
+    self, ConnectionStatus, NetcodeClient, NetcodeError, NetcodeEvent, PlayerHandle,
+    PredictionStats, RingBufferStore,
+};
+use crate::session::{Session, SessionError};
 use ggez::event::EventHandler;
 use ggez::event::{KeyCode, KeyMods};
-use ggez::{graphics, Context, GameResult};
+use ggez::{graphics, Context, GameError, GameResult};
 use serde::{Deserialize, Serialize};
-use std::io::ErrorKind;
 use std::time::Instant;
 
+// peers running a different build could simulate differently, so they aren't allowed to connect
+pub const GAME_ID: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+// every match gets recorded here, overwriting the last one
+pub const REPLAY_PATH: &str = "last_match.replay";
+pub const NETCODE_SETTINGS: NetcodeSettings = NetcodeSettings {
+    input_delay: 1,
+    allowed_rollback: 9,
+    tick_rate: 60,
+};
+
 pub struct RollbackRunner {
     current_state: GameState,
-    delay_client: NetcodeClient<GameInput, GameState>,
+    session: Session<NetClient, GameInput, GameState, RingBufferStore<GameState>>,
     input_state: i32,
-    player1: bool,
-    client: TestNetClient,
     ping: f32,
     start_time: Instant,
     local_handle: PlayerHandle,
@@ -31,11 +42,11 @@ struct InputData {
     input: Vec<InputTiming>,
 }
 
+// the session only carries netcode packets, so pings go over the control channel
 #[derive(Serialize, Deserialize, Debug, Clone)]
-enum RollbackPacket {
+enum PingPacket {
     Ping(u128),
     Pong(u128),
-    Netcode(netcode::Packet<GameInput>),
 }
 
 impl<'a> netcode::RollbackableGameState for GameState {
@@ -53,29 +64,74 @@ impl<'a> netcode::RollbackableGameState for GameState {
     fn load_state(&mut self, load: Self::SavedState) {
         *self = load;
     }
+    fn checksum(&self) -> Option<u64> {
+        Some(GameState::checksum(self))
+    }
+}
+
+fn netcode_error(error: NetcodeError) -> GameError {
+    GameError::EventLoopError(format!("netcode error: {}", error))
+}
+fn session_error(error: SessionError) -> GameError {
+    GameError::EventLoopError(format!("session error: {}", error))
 }
 
 impl RollbackRunner {
-    pub fn new(ctx: &mut Context, player1: bool, client: TestNetClient) -> RollbackRunner {
-        let mut delay_client = NetcodeClient::new(100);
+    pub fn new(ctx: &mut Context, player1: bool, client: NetClient) -> RollbackRunner {
+        // the settings are fixed for the whole match, so the store never needs more room
+        let mut delay_client = NetcodeClient::with_save_state_store(
+            100,
+            RingBufferStore::with_capacity(NETCODE_SETTINGS.allowed_rollback + 1),
+        );
+        delay_client.set_input_delay(NETCODE_SETTINGS.input_delay);
+        delay_client.set_allowed_rollback(NETCODE_SETTINGS.allowed_rollback);
         let (local_player_id, network_player_id) = if player1 { (0, 1) } else { (1, 0) };
 
-        let local_handle = delay_client.add_local_player(local_player_id);
-        let network_handle = delay_client.add_net_player(network_player_id);
+        let local_handle = delay_client.add_local_player(local_player_id).unwrap();
+        let network_handle = delay_client.add_net_player(network_player_id).unwrap();
+        delay_client.start_recording().unwrap();
+
+        // the udp socket is connected as soon as the client is made
+        let peer = client.peer_addr().unwrap();
+        let mut session = Session::new(client, delay_client);
+        session.add_peer(peer);
 
         // Load/create resources such as images here.
         RollbackRunner {
             current_state: GameState::new(ctx),
             input_state: 0,
-            delay_client: delay_client,
-            player1,
-            client,
+            session,
             ping: 0.0,
             start_time: Instant::now(),
             local_handle,
             network_handle,
         }
     }
+
+    fn poll_pings(&mut self, current_time: u128) -> GameResult<()> {
+        while let Some(packet) = self.session.transport_mut().try_read_tcp::<PingPacket>()? {
+            match packet {
+                PingPacket::Ping(ping_time) => {
+                    self.session
+                        .transport_mut()
+                        .write_tcp(&PingPacket::Pong(ping_time))?;
+                }
+                PingPacket::Pong(pong_time) => {
+                    let ping_time = current_time - pong_time;
+                    //
+                    self.ping = self.ping * 0.9 + ping_time as f32 * 0.1;
+                    self.session
+                        .client_mut()
+                        .set_network_delay(
+                            ((self.ping + 3.0) / 32.0).ceil() as usize,
+                            self.network_handle,
+                        )
+                        .map_err(netcode_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl EventHandler for RollbackRunner {
@@ -83,64 +139,40 @@ impl EventHandler for RollbackRunner {
         let start_time = Instant::now();
         let current_time = (start_time - self.start_time).as_millis();
 
-        let mut count = 0;
-        'poll_packets: loop {
-            match self.client.recv::<RollbackPacket>() {
-                Ok(packet) => match packet {
-                    RollbackPacket::Ping(ping_time) => {
-                        self.client.send(&RollbackPacket::Pong(ping_time)).unwrap();
-                    }
-                    RollbackPacket::Pong(pong_time) => {
-                        let ping_time = current_time - pong_time;
-                        //
-                        self.ping = self.ping * 0.9 + ping_time as f32 * 0.1;
-                        self.delay_client.set_network_delay(
-                            ((self.ping + 3.0) / 32.0).ceil() as usize,
-                            self.network_handle,
-                        );
-                    }
-                    RollbackPacket::Netcode(input) => {
-                        if let Some(packet) = self.delay_client.handle_packet(input) {
-                            self.client.send(&RollbackPacket::Netcode(packet)).unwrap();
-                        };
-                    }
-                },
-                Err(e) if e.kind() == ErrorKind::WouldBlock => break 'poll_packets,
-                Err(e) => {
-                    panic!("{:?}", e);
-                }
-            }
-            count += 1;
-            if count % 20 == 0 {
-                dbg!(count);
-            }
-        }
+        self.poll_pings(current_time)?;
+        // bad packets from the other side get dropped by the session instead of taking us down
+        self.session.poll().map_err(session_error)?;
 
-        let fps = if self.player1 { 60 } else { 60 };
-        if ggez::timer::check_update_time(ctx, fps) {
-            if let Some(packet) = self.delay_client.handle_local_input(
-                GameInput {
-                    x_axis: self.input_state,
-                },
-                self.local_handle,
-            ) {
-                self.client.send(&RollbackPacket::Netcode(packet)).unwrap();
-            }
+        if ggez::timer::check_update_time(ctx, NETCODE_SETTINGS.tick_rate) {
+            self.session
+                .handle_local_input(
+                    GameInput {
+                        x_axis: self.input_state,
+                    },
+                    self.local_handle,
+                )
+                .map_err(session_error)?;
 
             let start_time = Instant::now();
             let current_time = (start_time - self.start_time).as_millis();
-            self.client
-                .send(&RollbackPacket::Ping(current_time))
-                .unwrap();
+            self.session
+                .transport_mut()
+                .write_tcp(&PingPacket::Ping(current_time))?;
 
-            let (client, game_state) = (&mut self.delay_client, &mut self.current_state);
+            self.session
+                .update(&mut self.current_state)
+                .map_err(session_error)?;
+        }
 
-            if let Some(packet) = client.update(game_state) {
-                self.client.send(&RollbackPacket::Netcode(packet)).unwrap();
+        for event in self.session.client_mut().poll_events() {
+            match event {
+                NetcodeEvent::PeerInterrupted(_)
+                | NetcodeEvent::PeerResumed(_)
+                | NetcodeEvent::PeerDisconnected(_)
+                | NetcodeEvent::Desync(_) => println!("{:?}", event),
+                _ => (),
             }
         }
-
-        self.client.send_queued()?;
         Ok(())
     }
     fn key_down_event(
@@ -156,42 +188,23 @@ impl EventHandler for RollbackRunner {
                 KeyCode::Right => 1,
                 _ => 0,
             };
-            match keycode {
-                KeyCode::D => self.client.delay += std::time::Duration::from_millis(10),
-                KeyCode::A => {
-                    if self.client.delay >= std::time::Duration::from_millis(10) {
-                        self.client.delay -= std::time::Duration::from_millis(10)
-                    }
-                }
-                KeyCode::W => self.client.packet_loss += 0.05,
-                KeyCode::S => self.client.packet_loss -= 0.05,
-                KeyCode::E => self
-                    .delay_client
-                    .set_allowed_rollback(self.delay_client.allowed_rollback() + 1),
-                KeyCode::Q => self
-                    .delay_client
-                    .set_allowed_rollback(self.delay_client.allowed_rollback() - 1),
-                KeyCode::Z => self
-                    .delay_client
-                    .set_input_delay(self.delay_client.input_delay() + 1),
-                KeyCode::C => self
-                    .delay_client
-                    .set_input_delay(self.delay_client.input_delay() - 1),
-
-                _ => (),
-            };
-
-            self.delay_client.set_packet_buffer_size(
-                10.max(self.delay_client.input_delay() + self.delay_client.allowed_rollback()),
-            );
+            // rollback and input delay are part of the handshake, so they're fixed by
+            // NETCODE_SETTINGS, changing them here would desync us from the other side
+            if keycode == KeyCode::R {
+                self.session.client_mut().reset_stats();
+            }
+        }
+    }
 
-            self.client.packet_loss = self.client.packet_loss.max(0.0).min(1.0);
-            self.client.delay = self
-                .client
-                .delay
-                .max(std::time::Duration::from_millis(0))
-                .min(std::time::Duration::from_millis(100));
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        if let Some(replay) = self.session.client_mut().stop_recording() {
+            let saved = std::fs::File::create(REPLAY_PATH)
+                .and_then(|file| replay.save(std::io::BufWriter::new(file)));
+            if let Err(e) = saved {
+                println!("Couldn't save replay: {}", e);
+            }
         }
+        false
     }
 
     fn key_up_event(&mut self, _ctx: &mut Context, keycode: KeyCode, _keymod: KeyMods) {
@@ -205,10 +218,11 @@ impl EventHandler for RollbackRunner {
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, graphics::BLACK);
+        let delay_client = self.session.client();
         self.current_state.draw(ctx, 100.0)?;
         graphics::draw(
             ctx,
-            &graphics::Text::new(format!("Delay: {:.2}f", self.delay_client.input_delay())),
+            &graphics::Text::new(format!("Delay: {:.2}f", delay_client.input_delay())),
             graphics::DrawParam::default().dest([30.0, 200.0]),
         )?;
         graphics::draw(
@@ -220,7 +234,7 @@ impl EventHandler for RollbackRunner {
             ctx,
             &graphics::Text::new(format!(
                 "Current Frame: f{:.2}",
-                self.delay_client.current_frame()
+                delay_client.current_frame()
             )),
             graphics::DrawParam::default().dest([30.0, 300.0]),
         )?;
@@ -228,7 +242,9 @@ impl EventHandler for RollbackRunner {
             ctx,
             &graphics::Text::new(format!(
                 "Network Delay: {:.2}f",
-                self.delay_client.get_network_delay(self.network_handle)
+                delay_client
+                    .get_network_delay(self.network_handle)
+                    .map_err(netcode_error)?
             )),
             graphics::DrawParam::default().dest([30.0, 350.0]),
         )?;
@@ -236,35 +252,96 @@ impl EventHandler for RollbackRunner {
             ctx,
             &graphics::Text::new(format!(
                 "Allowed Rollback: {:.0}f",
-                self.delay_client.allowed_rollback()
+                delay_client.allowed_rollback()
             )),
             graphics::DrawParam::default().dest([30.0, 400.0]),
         )?;
-
+        let stats = delay_client.stats();
+        let prediction_stats: PredictionStats = stats
+            .predictions
+            .get(&self.network_handle)
+            .cloned()
+            .unwrap_or_default();
         graphics::draw(
             ctx,
             &graphics::Text::new(format!(
-                "Faked Network Delay (ms): {:.2}",
-                self.client.delay.as_millis()
+                "Rollbacks: {} ({}f resimulated)",
+                stats.rollbacks, stats.resimulated_frames
             )),
-            graphics::DrawParam::default().dest([300.0, 200.0]),
+            graphics::DrawParam::default().dest([30.0, 450.0]),
+        )?;
+        graphics::draw(
+            ctx,
+            &graphics::Text::new(format!(
+                "Mispredictions: {}/{}",
+                prediction_stats.mispredictions, prediction_stats.predictions
+            )),
+            graphics::DrawParam::default().dest([30.0, 500.0]),
+        )?;
+        graphics::draw(
+            ctx,
+            &graphics::Text::new(format!(
+                "Stalled/Skipped: {}f/{}f",
+                stats.stalled_frames, stats.skipped_frames
+            )),
+            graphics::DrawParam::default().dest([30.0, 550.0]),
         )?;
+
         graphics::draw(
             ctx,
             &graphics::Text::new(format!(
-                "Faked Packet Loss (%): {:.2}",
-                self.client.packet_loss * 100.0
+                "Dropped Packets: {}",
+                self.session.dropped_packets()
             )),
-            graphics::DrawParam::default().dest([300.0, 250.0]),
+            graphics::DrawParam::default().dest([300.0, 200.0]),
         )?;
+        if let Some((_, error)) = self.session.last_dropped_packet() {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new(format!("Last Dropped: {}", error)),
+                graphics::DrawParam::default().dest([300.0, 250.0]),
+            )?;
+        }
         graphics::draw(
             ctx,
             &graphics::Text::new(format!(
                 "Buffer Size (f): {}",
-                self.delay_client.packet_buffer_size(),
+                delay_client.packet_buffer_size(),
             )),
             graphics::DrawParam::default().dest([300.0, 300.0]),
         )?;
+        graphics::draw(
+            ctx,
+            &graphics::Text::new(format!(
+                "Time Sync: {:.2}f",
+                delay_client.time_sync_recommendation(),
+            )),
+            graphics::DrawParam::default().dest([300.0, 400.0]),
+        )?;
+        if delay_client
+            .connection_status(self.network_handle)
+            .map_err(netcode_error)?
+            == ConnectionStatus::Interrupted
+        {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new("Connection Interrupted"),
+                graphics::DrawParam::default().dest([300.0, 450.0]),
+            )?;
+        }
+        if let Some(desync) = delay_client.desync() {
+            graphics::draw(
+                ctx,
+                &graphics::Text::new(format!(
+                    "Desync with p{} on f{}: {:x} vs {:x}",
+                    desync.player.id(),
+                    desync.frame,
+                    desync.local_checksum,
+                    desync.remote_checksum
+                )),
+                graphics::DrawParam::default().dest([300.0, 350.0]),
+            )?;
+        }
         graphics::present(ctx)
     }
 }
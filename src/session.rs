@@ -0,0 +1,485 @@
+use crate::net_client::Transport;
+use crate::netcode::{
+    NetInput, NetcodeClient, NetcodeError, NetcodeRequest, Packet, PlayerHandle,
+    RollbackableGameState, SaveStateStore, SpectatorHandle, UpdateRequests,
+};
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::io;
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    Netcode(NetcodeError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::Io(error) => write!(f, "transport error: {}", error),
+            SessionError::Netcode(error) => write!(f, "netcode error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(error: io::Error) -> Self {
+        SessionError::Io(error)
+    }
+}
+impl From<NetcodeError> for SessionError {
+    fn from(error: NetcodeError) -> Self {
+        SessionError::Netcode(error)
+    }
+}
+
+// moves the packets of a NetcodeClient through any transport, so all that's left is the game
+pub struct Session<T: Transport, Input, GameState, Store = HashMap<usize, GameState>> {
+    transport: T,
+    client: NetcodeClient<Input, GameState, Store>,
+    peers: Vec<T::Peer>,
+    spectators: HashMap<T::Peer, SpectatorHandle>,
+    dropped_packets: usize,
+    last_dropped_packet: Option<(T::Peer, SessionError)>,
+}
+
+impl<T, Input, GameState, Store> Session<T, Input, GameState, Store>
+where
+    T: Transport,
+    Input: NetInput + Clone + Default + PartialEq + Debug,
+    GameState: Debug,
+    Store: SaveStateStore<GameState>,
+{
+    pub fn new(transport: T, client: NetcodeClient<Input, GameState, Store>) -> Self {
+        Self {
+            transport,
+            client,
+            peers: Vec::new(),
+            spectators: HashMap::new(),
+            dropped_packets: 0,
+            last_dropped_packet: None,
+        }
+    }
+
+    pub fn client(&self) -> &NetcodeClient<Input, GameState, Store> {
+        &self.client
+    }
+    pub fn client_mut(&mut self) -> &mut NetcodeClient<Input, GameState, Store> {
+        &mut self.client
+    }
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+    // packets that couldn't be decoded or that the client turned away
+    pub fn dropped_packets(&self) -> usize {
+        self.dropped_packets
+    }
+    // who sent the last dropped packet, and why it was dropped
+    pub fn last_dropped_packet(&self) -> Option<(T::Peer, &SessionError)> {
+        self.last_dropped_packet
+            .as_ref()
+            .map(|(peer, error)| (*peer, error))
+    }
+
+    // every peer gets our inputs, and packets from anyone else are ignored
+    pub fn add_peer(&mut self, peer: T::Peer) {
+        if !self.peers.contains(&peer) {
+            self.peers.push(peer);
+        }
+    }
+    pub fn remove_peer(&mut self, peer: T::Peer) {
+        self.peers.retain(|added| *added != peer);
+    }
+
+    pub fn add_spectator(&mut self, peer: T::Peer) -> SpectatorHandle {
+        let spectator = self.client.add_spectator();
+        self.spectators.insert(peer, spectator);
+        spectator
+    }
+    pub fn remove_spectator(&mut self, peer: T::Peer) -> Result<(), SessionError> {
+        if let Some(spectator) = self.spectators.remove(&peer) {
+            self.client.remove_spectator(spectator)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, peer: T::Peer, packet: &Packet<Input>) -> Result<(), SessionError> {
+        let data = bincode::serialize(packet).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bincode serialization failed before sending a packet",
+            )
+        })?;
+        self.transport.send_to(peer, &data)?;
+        Ok(())
+    }
+    fn broadcast(&mut self, packet: &Packet<Input>) -> Result<(), SessionError> {
+        for peer in self.peers.clone() {
+            self.send(peer, packet)?;
+        }
+        Ok(())
+    }
+
+    // hands every waiting packet to the client, and answers whoever sent it,
+    // a bad packet from a peer shouldn't take us down with it, so those are dropped
+    pub fn poll(&mut self) -> Result<(), SessionError> {
+        self.transport.poll()?;
+        while let Some((peer, data)) = self.transport.recv_from()? {
+            let spectator = self.spectators.get(&peer).cloned();
+            if spectator.is_none() && !self.peers.contains(&peer) {
+                // not part of this session
+                continue;
+            }
+            let packet: Packet<Input> = match bincode::deserialize(&data) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    let error = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "bincode deserialization failed while receiving a packet",
+                    );
+                    self.drop_packet(peer, error.into());
+                    continue;
+                }
+            };
+
+            let response = match spectator {
+                Some(spectator) => self
+                    .client
+                    .handle_spectator_packet(spectator, packet)
+                    .map(|_| None),
+                None => self.client.handle_packet(packet),
+            };
+            match response {
+                Ok(Some(response)) => self.send(peer, &response)?,
+                Ok(None) => (),
+                Err(e) => self.drop_packet(peer, e.into()),
+            }
+        }
+        Ok(())
+    }
+    fn drop_packet(&mut self, peer: T::Peer, error: SessionError) {
+        self.dropped_packets += 1;
+        self.last_dropped_packet = Some((peer, error));
+    }
+
+    pub fn handle_local_input(
+        &mut self,
+        data: Input,
+        player: PlayerHandle,
+    ) -> Result<(), SessionError> {
+        if let Some(packet) = self.client.handle_local_input(data, player)? {
+            self.broadcast(&packet)?;
+        }
+        Ok(())
+    }
+
+    pub fn update<Game: RollbackableGameState<SavedState = GameState, Input = Input>>(
+        &mut self,
+        game: &mut Game,
+    ) -> Result<(), SessionError> {
+        if let Some(packet) = self.client.update(game)? {
+            self.broadcast(&packet)?;
+        }
+        self.send_spectator_packets()
+    }
+
+    // the requests still have to be run through the client, see NetcodeClient::update_requests
+    pub fn update_requests(&mut self) -> Result<Vec<NetcodeRequest>, SessionError> {
+        let UpdateRequests { requests, packet } = self.client.update_requests()?;
+        if let Some(packet) = packet {
+            self.broadcast(&packet)?;
+        }
+        self.send_spectator_packets()?;
+        Ok(requests)
+    }
+
+    fn send_spectator_packets(&mut self) -> Result<(), SessionError> {
+        if self.spectators.is_empty() {
+            return Ok(());
+        }
+        let packets = self.client.spectator_packets();
+        // the client drops spectators that fell too far behind, so we stop listening to them too
+        let client = &self.client;
+        self.spectators
+            .retain(|_, handle| client.spectator_next_frame(*handle).is_ok());
+        for (spectator, packet) in packets {
+            let peer = self
+                .spectators
+                .iter()
+                .find(|(_, handle)| **handle == spectator)
+                .map(|(peer, _)| *peer);
+            if let Some(peer) = peer {
+                // a spectator we can't reach anymore shouldn't end the match for the players
+                if self.send(peer, &packet).is_err() {
+                    self.remove_spectator(peer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net_client::{MemoryHub, MemoryTransport};
+    use crate::netcode::{InputSet, ReplayPlayer, RingBufferStore, WireFrame};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    struct TestInput(i8);
+
+    impl NetInput for TestInput {
+        fn layout_probes() -> Vec<Self> {
+            vec![TestInput(-1), TestInput(1)]
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct TestGame {
+        positions: Vec<i64>,
+        // the state after every frame that was run, resimulating overwrites it
+        history: BTreeMap<usize, Vec<i64>>,
+    }
+
+    impl TestGame {
+        fn new(players: usize) -> Self {
+            Self {
+                positions: vec![0; players],
+                history: BTreeMap::new(),
+            }
+        }
+    }
+
+    impl RollbackableGameState for TestGame {
+        type Input = TestInput;
+        type SavedState = Vec<i64>;
+        fn advance_frame(&mut self, input: InputSet<'_, Self::Input>) {
+            for (position, inputs) in self.positions.iter_mut().zip(input.inputs.iter()) {
+                let current = inputs.last().unwrap().0 as i64;
+                // depends on the held inputs too, so bad input windows show up as desyncs
+                let held: i64 = inputs.iter().map(|input| input.0 as i64).sum();
+                *position = *position * 3 % 1_000_003 + current * 7 + held;
+            }
+            self.history.insert(input.frame, self.positions.clone());
+        }
+        fn save_state(&self) -> Self::SavedState {
+            self.positions.clone()
+        }
+        fn load_state(&mut self, load: Self::SavedState) {
+            self.positions = load;
+        }
+        fn checksum(&self) -> Option<u64> {
+            Some(self.positions.iter().fold(0, |acc, position| {
+                acc.wrapping_mul(31).wrapping_add(*position as u64)
+            }))
+        }
+    }
+
+    // changes direction every so often, differently for every player
+    fn scripted_input(frame: usize, player: usize) -> TestInput {
+        TestInput((((frame / (5 + player * 3)) + player) % 3) as i8 - 1)
+    }
+
+    type TestSession = Session<MemoryTransport, TestInput, Vec<i64>, RingBufferStore<Vec<i64>>>;
+
+    // each session alongside the handle of its local player
+    fn create_sessions(hub: &MemoryHub, players: usize) -> Vec<(TestSession, PlayerHandle)> {
+        let transports: Vec<_> = (0..players).map(|_| hub.add_peer()).collect();
+        let ids: Vec<_> = transports.iter().map(|transport| transport.id()).collect();
+        transports
+            .into_iter()
+            .enumerate()
+            .map(|(local, transport)| {
+                // just enough for the default allowed rollback of 9 plus the current frame
+                let mut client =
+                    NetcodeClient::with_save_state_store(4, RingBufferStore::with_capacity(10));
+                let mut local_handle = None;
+                for player in 0..players {
+                    if player == local {
+                        local_handle = Some(client.add_local_player(player).unwrap());
+                    } else {
+                        client.add_net_player(player).unwrap();
+                    }
+                }
+                client.start_recording().unwrap();
+                let mut session = Session::new(transport, client);
+                for id in ids.iter().filter(|id| **id != ids[local]) {
+                    session.add_peer(*id);
+                }
+                (session, local_handle.unwrap())
+            })
+            .collect()
+    }
+
+    // steps every session one frame at a time, with the hub clock moving a frame in between
+    fn run_lockstep(
+        hub: &MemoryHub,
+        sessions: &mut [(TestSession, PlayerHandle)],
+        frames: usize,
+    ) -> Vec<TestGame> {
+        let mut games: Vec<_> = sessions
+            .iter()
+            .map(|_| TestGame::new(sessions.len()))
+            .collect();
+        for tick in 0..frames {
+            for (local, ((session, handle), game)) in
+                sessions.iter_mut().zip(games.iter_mut()).enumerate()
+            {
+                session.poll().unwrap();
+                session
+                    .handle_local_input(scripted_input(tick, local), *handle)
+                    .unwrap();
+                session.update(game).unwrap();
+            }
+            hub.advance(FRAME);
+        }
+        games
+    }
+
+    // every session should agree with a plain run of the confirmed inputs, up to the last frame everyone confirmed
+    fn assert_sessions_agree(sessions: &mut [(TestSession, PlayerHandle)], games: &[TestGame]) {
+        let confirmed = sessions
+            .iter()
+            .map(|(session, _)| session.client().confirmed_frame().unwrap())
+            .min()
+            .unwrap();
+        assert!(confirmed > 100, "only confirmed up to frame {}", confirmed);
+
+        let replays: Vec<_> = sessions
+            .iter_mut()
+            .map(|(session, _)| session.client_mut().stop_recording().unwrap())
+            .collect();
+        let mut expected = TestGame::new(sessions.len());
+        let mut player = ReplayPlayer::new(replays[0].clone());
+        while player.step(&mut expected) {}
+
+        for (replay, game) in replays.iter().zip(games) {
+            for (inputs, expected_inputs) in replay.inputs.iter().zip(replays[0].inputs.iter()) {
+                let frames = inputs.len().min(expected_inputs.len());
+                assert_eq!(inputs[..frames], expected_inputs[..frames]);
+            }
+            for frame in 0..=confirmed {
+                assert_eq!(
+                    game.history.get(&frame),
+                    expected.history.get(&frame),
+                    "frame {}",
+                    frame
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn two_players_without_latency() {
+        let hub = MemoryHub::new();
+        let mut sessions = create_sessions(&hub, 2);
+
+        let games = run_lockstep(&hub, &mut sessions, 200);
+
+        assert_sessions_agree(&mut sessions, &games);
+    }
+
+    #[test]
+    fn two_players_rollback_through_latency() {
+        let hub = MemoryHub::new();
+        hub.set_latency(FRAME * 3);
+        let mut sessions = create_sessions(&hub, 2);
+
+        let games = run_lockstep(&hub, &mut sessions, 200);
+
+        assert!(sessions
+            .iter()
+            .all(|(session, _)| session.client().stats().rollbacks > 0));
+        assert_sessions_agree(&mut sessions, &games);
+    }
+
+    #[test]
+    fn two_players_recover_from_packet_loss() {
+        let hub = MemoryHub::new();
+        hub.set_latency(FRAME * 2);
+        hub.set_drop_every(Some(4));
+        let mut sessions = create_sessions(&hub, 2);
+
+        let games = run_lockstep(&hub, &mut sessions, 300);
+
+        assert_sessions_agree(&mut sessions, &games);
+    }
+
+    #[test]
+    fn bad_packets_are_dropped() {
+        let hub = MemoryHub::new();
+        let mut sessions = create_sessions(&hub, 2);
+        let mut stranger = hub.add_peer();
+        let first = sessions[0].0.transport().id();
+        let second_handle = sessions[1].1;
+        sessions[0].0.add_peer(stranger.id());
+
+        // garbage, then an ack for a player that isn't ours
+        stranger.send_to(first, &[0xff; 7]).unwrap();
+        let wrong_player = bincode::serialize(&Packet::<TestInput>::InputAck(
+            second_handle,
+            WireFrame::new(0),
+        ))
+        .unwrap();
+        stranger.send_to(first, &wrong_player).unwrap();
+        // packets from the real peer still have to get through
+        sessions[1]
+            .0
+            .handle_local_input(TestInput(1), second_handle)
+            .unwrap();
+        hub.advance(FRAME);
+
+        sessions[0].0.poll().unwrap();
+
+        assert_eq!(sessions[0].0.dropped_packets(), 2);
+        match sessions[0].0.last_dropped_packet() {
+            Some((peer, SessionError::Netcode(NetcodeError::NotLocalPlayer(player)))) => {
+                assert_eq!(peer, stranger.id());
+                assert_eq!(*player, second_handle);
+            }
+            dropped => panic!("expected the wrong ack to be dropped, got {:?}", dropped),
+        }
+        assert_eq!(
+            sessions[0].0.client().last_confirmed_frame(second_handle),
+            Ok(Some(0))
+        );
+    }
+
+    #[test]
+    fn spectators_cant_end_the_match() {
+        let hub = MemoryHub::new();
+        let mut sessions = create_sessions(&hub, 2);
+        // one that never acks, and one that isn't on the hub at all
+        let silent = hub.add_peer();
+        let silent_handle = sessions[0].0.add_spectator(silent.id());
+        let missing_handle = sessions[0].0.add_spectator(silent.id() + 100);
+
+        let games = run_lockstep(&hub, &mut sessions, 200);
+
+        let client = sessions[0].0.client();
+        assert!(client.spectator_next_frame(silent_handle).is_err());
+        assert!(client.spectator_next_frame(missing_handle).is_err());
+        assert_sessions_agree(&mut sessions, &games);
+    }
+
+    #[test]
+    fn three_players_on_a_hub() {
+        let hub = MemoryHub::new();
+        hub.set_latency(FRAME * 2);
+        let mut sessions = create_sessions(&hub, 3);
+
+        let games = run_lockstep(&hub, &mut sessions, 200);
+
+        assert_sessions_agree(&mut sessions, &games);
+    }
+}